@@ -0,0 +1,280 @@
+//! Private admin API, used by gateway operators to introspect the gateway state.
+//!
+//! These routes must not be exposed to public requests.
+
+use std::collections::HashMap;
+
+use axum::{
+    extract::{FromRef, Path, State},
+    http::StatusCode,
+    routing, Router,
+};
+use semver::Version;
+use serde::Serialize;
+use serde_json::json;
+use thegraph_core::{AllocationId, BlockNumber, DeploymentId, IndexerId, SubgraphId};
+
+use crate::{
+    chain::Reorg,
+    chains::Chains,
+    config::{SelectionConfig, SelectionParams},
+    json::{json_response, JsonResponse},
+    network::{Indexing, IndexingError, IndexingId, NetworkService, ResolutionError},
+    receipts::ledger::ReceiptLedger,
+};
+
+#[derive(Clone)]
+struct AdminState {
+    network: NetworkService,
+    chains: &'static Chains,
+    selection: &'static SelectionConfig,
+    receipt_ledger: Option<&'static ReceiptLedger>,
+}
+
+impl FromRef<AdminState> for NetworkService {
+    fn from_ref(state: &AdminState) -> Self {
+        state.network.clone()
+    }
+}
+
+impl FromRef<AdminState> for &'static Chains {
+    fn from_ref(state: &AdminState) -> Self {
+        state.chains
+    }
+}
+
+impl FromRef<AdminState> for &'static SelectionConfig {
+    fn from_ref(state: &AdminState) -> Self {
+        state.selection
+    }
+}
+
+impl FromRef<AdminState> for Option<&'static ReceiptLedger> {
+    fn from_ref(state: &AdminState) -> Self {
+        state.receipt_ledger
+    }
+}
+
+/// Create the admin API router.
+pub fn router(
+    network: NetworkService,
+    chains: &'static Chains,
+    selection: &'static SelectionConfig,
+    receipt_ledger: Option<&'static ReceiptLedger>,
+) -> Router {
+    Router::new()
+        .route(
+            "/network/subgraphs/id/:subgraph_id",
+            routing::get(handle_subgraph),
+        )
+        .route(
+            "/network/deployments/id/:deployment_id",
+            routing::get(handle_deployment),
+        )
+        .route("/chains/:chain/reorgs", routing::get(handle_reorgs))
+        .route("/selection", routing::get(handle_selection))
+        .route(
+            "/receipts/allocations/:allocation",
+            routing::get(handle_allocation_receipts),
+        )
+        .with_state(AdminState {
+            network,
+            chains,
+            selection,
+            receipt_ledger,
+        })
+}
+
+#[derive(Serialize)]
+struct SubgraphView {
+    id: SubgraphId,
+    stale: bool,
+    chain: String,
+    start_block: BlockNumber,
+    versions: Vec<DeploymentId>,
+    /// Effective selection parameters of the latest version, used to select the version for
+    /// queries by subgraph ID. The queries then use the parameters of the selected version.
+    selection: SelectionParams,
+    indexings: Vec<IndexingView>,
+}
+
+#[derive(Serialize)]
+struct DeploymentView {
+    id: DeploymentId,
+    stale: bool,
+    chain: String,
+    start_block: BlockNumber,
+    subgraphs: Vec<SubgraphId>,
+    /// Effective selection parameters, for queries by deployment ID
+    selection: SelectionParams,
+    indexings: Vec<IndexingView>,
+}
+
+#[derive(Serialize)]
+struct IndexingView {
+    indexer: IndexerId,
+    deployment: DeploymentId,
+    #[serde(flatten)]
+    status: IndexingStatus,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+enum IndexingStatus {
+    Available {
+        url: String,
+        indexer_service_version: Version,
+        graph_node_version: Version,
+        tap_support: bool,
+        staked_tokens: String,
+        largest_allocation: AllocationId,
+        total_allocated_tokens: String,
+        latest_block: BlockNumber,
+        min_block: Option<BlockNumber>,
+        cost_model: bool,
+    },
+    /// The indexing was excluded by the network service.
+    Excluded {
+        /// The reason reported to the query processing pipeline.
+        reason: String,
+        /// The underlying resolution error.
+        error: String,
+    },
+}
+
+async fn handle_subgraph(
+    State(network): State<NetworkService>,
+    State(selection): State<&'static SelectionConfig>,
+    Path(id): Path<SubgraphId>,
+) -> Result<JsonResponse, (StatusCode, String)> {
+    let view = {
+        // Note: holding watch::Ref until the view is constructed
+        let snapshot = network.snapshot();
+        match snapshot.subgraphs.get(&id) {
+            None => return Err((StatusCode::NOT_FOUND, "subgraph not found".into())),
+            Some(Err(subgraph_err)) => {
+                return Ok(json_response(
+                    [],
+                    json!({ "id": id, "error": subgraph_err.to_string() }),
+                ))
+            }
+            Some(Ok(subgraph)) => SubgraphView {
+                id: subgraph.id,
+                stale: snapshot.stale,
+                chain: subgraph.chain.clone(),
+                start_block: subgraph.start_block,
+                versions: subgraph.versions.clone(),
+                selection: selection.params(&subgraph.chain, &subgraph.versions[0]),
+                indexings: indexing_views(&subgraph.indexings),
+            },
+        }
+    };
+    Ok(json_response([], serde_json::to_value(view).unwrap()))
+}
+
+async fn handle_deployment(
+    State(network): State<NetworkService>,
+    State(selection): State<&'static SelectionConfig>,
+    Path(id): Path<DeploymentId>,
+) -> Result<JsonResponse, (StatusCode, String)> {
+    let view = {
+        // Note: holding watch::Ref until the view is constructed
+        let snapshot = network.snapshot();
+        match snapshot.deployments.get(&id) {
+            None => return Err((StatusCode::NOT_FOUND, "deployment not found".into())),
+            Some(Err(deployment_err)) => {
+                return Ok(json_response(
+                    [],
+                    json!({ "id": id, "error": deployment_err.to_string() }),
+                ))
+            }
+            Some(Ok(deployment)) => DeploymentView {
+                id: deployment.id,
+                stale: snapshot.stale,
+                chain: deployment.chain.clone(),
+                start_block: deployment.start_block,
+                subgraphs: deployment.subgraphs.iter().copied().collect(),
+                selection: selection.params(&deployment.chain, &deployment.id),
+                indexings: indexing_views(&deployment.indexings),
+            },
+        }
+    };
+    Ok(json_response([], serde_json::to_value(view).unwrap()))
+}
+
+/// Recent reorgs detected on the chain, starting from the latest.
+async fn handle_reorgs(
+    State(chains): State<&'static Chains>,
+    Path(chain): Path<String>,
+) -> Result<JsonResponse, (StatusCode, String)> {
+    let chain = chains
+        .get(&chain)
+        .ok_or_else(|| (StatusCode::NOT_FOUND, "chain not found".to_string()))?;
+    let reorgs: Vec<Reorg> = chain.read().reorgs().rev().cloned().collect();
+    Ok(json_response([], serde_json::to_value(reorgs).unwrap()))
+}
+
+/// Configured candidate selection parameters, with the default values applied.
+async fn handle_selection(State(selection): State<&'static SelectionConfig>) -> JsonResponse {
+    let view = json!({
+        "defaults": selection.default_params(),
+        "chains": selection.chains,
+        "deployments": selection.deployments,
+    });
+    json_response([], view)
+}
+
+/// Totals of the receipts issued for the allocation, from the receipt ledger.
+async fn handle_allocation_receipts(
+    State(receipt_ledger): State<Option<&'static ReceiptLedger>>,
+    Path(allocation): Path<AllocationId>,
+) -> Result<JsonResponse, (StatusCode, String)> {
+    let receipt_ledger = receipt_ledger.ok_or_else(|| {
+        (
+            StatusCode::NOT_FOUND,
+            "receipt ledger not enabled".to_string(),
+        )
+    })?;
+    let totals = receipt_ledger.totals(&allocation).ok_or_else(|| {
+        (
+            StatusCode::NOT_FOUND,
+            "no receipts for allocation".to_string(),
+        )
+    })?;
+    Ok(json_response(
+        [],
+        json!({ "allocation": allocation, "totals": totals }),
+    ))
+}
+
+fn indexing_views(
+    indexings: &HashMap<IndexingId, Result<Indexing, IndexingError>>,
+) -> Vec<IndexingView> {
+    let mut views: Vec<IndexingView> = indexings
+        .iter()
+        .map(|(id, indexing)| IndexingView {
+            indexer: id.indexer,
+            deployment: id.deployment,
+            status: match indexing {
+                Ok(indexing) => IndexingStatus::Available {
+                    url: indexing.indexer.url.to_string(),
+                    indexer_service_version: indexing.indexer.indexer_service_version.clone(),
+                    graph_node_version: indexing.indexer.graph_node_version.clone(),
+                    tap_support: indexing.indexer.tap_support,
+                    staked_tokens: indexing.indexer.staked_tokens.to_string(),
+                    largest_allocation: indexing.largest_allocation,
+                    total_allocated_tokens: indexing.total_allocated_tokens.to_string(),
+                    latest_block: indexing.progress.latest_block,
+                    min_block: indexing.progress.min_block,
+                    cost_model: indexing.cost_model.is_some(),
+                },
+                Err(err) => IndexingStatus::Excluded {
+                    reason: ResolutionError::from(err.clone()).to_string(),
+                    error: err.to_string(),
+                },
+            },
+        })
+        .collect();
+    views.sort_unstable_by_key(|view| (view.deployment, view.indexer));
+    views
+}
@@ -5,17 +5,21 @@ use std::{
 
 use anyhow::{anyhow, bail, ensure};
 use ordered_float::NotNan;
+use parking_lot::Mutex;
 use serde::Deserialize;
 use serde_with::serde_as;
 use thegraph_core::SubgraphId;
 use tokio::sync::watch;
 
+use crate::time::unix_timestamp;
+
 #[derive(Clone, Debug, Default)]
 pub struct AuthSettings {
     pub key: String,
     pub user: String,
     pub authorized_subgraphs: Vec<SubgraphId>,
     pub budget_usd: Option<NotNan<f64>>,
+    pub rate_limits: RateLimits,
 }
 
 impl AuthSettings {
@@ -33,7 +37,7 @@ impl AuthSettings {
 }
 
 #[serde_as]
-#[derive(Clone, Debug, Default, Deserialize)]
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
 pub struct APIKey {
     pub key: String,
     pub user: String,
@@ -45,9 +49,20 @@ pub struct APIKey {
     pub subgraphs: Vec<SubgraphId>,
     #[serde(default)]
     pub domains: Vec<String>,
+    #[serde(flatten)]
+    pub rate_limits: RateLimits,
+}
+
+/// Request rate limits of an API key. Unset limits are not enforced.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+pub struct RateLimits {
+    #[serde(default)]
+    pub max_requests_per_second: Option<u32>,
+    #[serde(default)]
+    pub max_requests_per_day: Option<u64>,
 }
 
-#[derive(Clone, Copy, Debug, Default, Deserialize)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
 #[serde(rename_all = "SCREAMING_SNAKE_CASE")]
 pub enum QueryStatus {
     #[default]
@@ -63,6 +78,7 @@ pub struct AuthContext {
     pub payment_required: bool,
     pub api_keys: watch::Receiver<HashMap<String, APIKey>>,
     pub special_api_keys: Arc<HashSet<String>>,
+    pub rate_limiter: Arc<ApiKeyRateLimiter>,
 }
 
 impl AuthContext {
@@ -78,6 +94,7 @@ impl AuthContext {
                 user: String::new(),
                 authorized_subgraphs: vec![],
                 budget_usd: None,
+                rate_limits: Default::default(),
             });
         }
 
@@ -108,8 +125,83 @@ impl AuthContext {
             user: api_key.user.clone(),
             authorized_subgraphs: api_key.subgraphs.clone(),
             budget_usd: api_key.max_budget_usd,
+            rate_limits: api_key.rate_limits,
         })
     }
+
+    /// Count the request against the API key rate limits, and check that they are not exceeded.
+    pub fn check_rate_limits(&self, auth: &AuthSettings) -> anyhow::Result<()> {
+        self.rate_limiter
+            .check(&auth.key, auth.rate_limits, unix_timestamp() / 1000)
+    }
+}
+
+/// Counts the requests made with each API key, over fixed windows of one second and one day.
+#[derive(Default)]
+pub struct ApiKeyRateLimiter {
+    state: Mutex<RateLimiterState>,
+}
+
+#[derive(Default)]
+struct RateLimiterState {
+    /// The day of the last cleanup, in days since Unix epoch.
+    day: u64,
+    counters: HashMap<String, RequestCounters>,
+}
+
+#[derive(Default)]
+struct RequestCounters {
+    second: u64,
+    second_count: u32,
+    day: u64,
+    day_count: u64,
+}
+
+impl ApiKeyRateLimiter {
+    /// Count a request made with the given API key at `now` (seconds since Unix epoch). Returns an
+    /// error, without counting the request, if any of the rate limits would be exceeded.
+    fn check(&self, key: &str, limits: RateLimits, now: u64) -> anyhow::Result<()> {
+        if limits == RateLimits::default() {
+            return Ok(());
+        }
+        let day = now / (60 * 60 * 24);
+
+        let mut state = self.state.lock();
+        // Remove the counters of API keys that haven't been used today.
+        if state.day != day {
+            state.day = day;
+            state.counters.retain(|_, counters| counters.day == day);
+        }
+
+        if !state.counters.contains_key(key) {
+            state.counters.insert(key.to_string(), Default::default());
+        }
+        let counters = state.counters.get_mut(key).unwrap();
+        if counters.second != now {
+            counters.second = now;
+            counters.second_count = 0;
+        }
+        if counters.day != day {
+            counters.day = day;
+            counters.day_count = 0;
+        }
+
+        if let Some(limit) = limits.max_requests_per_second {
+            ensure!(
+                counters.second_count < limit,
+                "rate limit exceeded for this API key, try again later"
+            );
+        }
+        if let Some(limit) = limits.max_requests_per_day {
+            ensure!(
+                counters.day_count < limit,
+                "daily request limit exceeded for this API key"
+            );
+        }
+        counters.second_count += 1;
+        counters.day_count += 1;
+        Ok(())
+    }
 }
 
 fn parse_api_key(token: &str) -> Option<[u8; 16]> {
@@ -146,7 +238,7 @@ pub fn is_domain_authorized<S: AsRef<str>>(authorized: &[S], origin: &str) -> bo
 mod tests {
     use alloy_primitives::hex;
 
-    use super::{is_domain_authorized, parse_api_key};
+    use super::{is_domain_authorized, parse_api_key, ApiKeyRateLimiter, RateLimits};
 
     #[test]
     fn parse_invalid_length_api_key() {
@@ -207,4 +299,31 @@ mod tests {
             assert!(is_domain_authorized(&[] as &[&str], input));
         }
     }
+
+    #[test]
+    fn api_key_rate_limits() {
+        //* Given
+        let limiter = ApiKeyRateLimiter::default();
+        let limits = RateLimits {
+            max_requests_per_second: Some(2),
+            max_requests_per_day: Some(3),
+        };
+        let day = 60 * 60 * 24;
+
+        //* Then
+        assert!(limiter.check("a", limits, day).is_ok());
+        assert!(limiter.check("a", limits, day).is_ok());
+        // per-second limit
+        assert!(limiter.check("a", limits, day).is_err());
+        // other API keys are counted separately
+        assert!(limiter.check("b", limits, day).is_ok());
+        assert!(limiter.check("a", limits, day + 1).is_ok());
+        // per-day limit
+        assert!(limiter.check("a", limits, day + 2).is_err());
+        assert!(limiter.check("a", limits, (2 * day) + 3).is_ok());
+        // unlimited
+        for _ in 0..10 {
+            assert!(limiter.check("c", RateLimits::default(), day).is_ok());
+        }
+    }
 }
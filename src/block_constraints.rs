@@ -1,4 +1,5 @@
 use std::{
+    borrow::Cow,
     collections::{BTreeMap, BTreeSet},
     fmt::Write as _,
 };
@@ -6,27 +7,31 @@ use std::{
 use anyhow::{anyhow, bail};
 use cost_model::Context;
 use graphql::{
-    graphql_parser::query::{OperationDefinition, Selection, SelectionSet, Text, Value},
+    graphql_parser::query::{
+        Directive, OperationDefinition, Query, Selection, SelectionSet, Text, Value,
+    },
     IntoStaticValue as _, StaticValue,
 };
 use itertools::Itertools as _;
 use serde_json::{self, json};
-use thegraph_core::{BlockHash, BlockNumber};
+use thegraph_core::{BlockHash, BlockNumber, BlockTimestamp};
 
 use crate::{
-    blocks::{BlockConstraint, UnresolvedBlock},
+    blocks::{Block, BlockConstraint, UnresolvedBlock},
     chain::Chain,
     errors::Error,
 };
 
 #[derive(Debug)]
 pub struct BlockRequirements {
-    /// required block range, for exact block constraints (`number` & `hash`)
+    /// required block range, for exact block constraints (`number`, `hash` & `timestamp_lte`)
     pub range: Option<(BlockNumber, BlockNumber)>,
     /// maximum `number_gte` constraint
     pub number_gte: Option<BlockNumber>,
     /// does the query benefit from using the latest block (contains NumberGTE or Unconstrained)
     pub latest: bool,
+    /// block numbers resolved for the `timestamp_lte` constraints
+    pub timestamps: BTreeMap<BlockTimestamp, BlockNumber>,
 }
 
 pub fn resolve_block_requirements(
@@ -38,7 +43,9 @@ pub fn resolve_block_requirements(
 
     let latest = constraints.iter().any(|c| match c {
         BlockConstraint::Unconstrained | BlockConstraint::NumberGTE(_) => true,
-        BlockConstraint::Hash(_) | BlockConstraint::Number(_) => false,
+        BlockConstraint::Hash(_)
+        | BlockConstraint::Number(_)
+        | BlockConstraint::TimestampLTE(_) => false,
     });
     let number_gte = constraints
         .iter()
@@ -48,11 +55,30 @@ pub fn resolve_block_requirements(
         })
         .max();
 
+    // Resolve timestamps to the latest block at or before them. Timestamps that can't be resolved
+    // from the known consensus blocks are rejected.
+    let mut timestamps: BTreeMap<BlockTimestamp, BlockNumber> = BTreeMap::new();
+    for constraint in &constraints {
+        if let BlockConstraint::TimestampLTE(timestamp) = constraint {
+            let number = resolve_timestamp(chain, *timestamp).ok_or(Error::BlockNotFound(
+                UnresolvedBlock::WithTimestampLTE(*timestamp),
+            ))?;
+            // Block constraints are GraphQL `Int` values.
+            if i32::try_from(number).is_err() {
+                return Err(Error::BadQuery(anyhow!(
+                    "block {number}, resolved for timestamp {timestamp}, is out of range"
+                )));
+            }
+            timestamps.insert(*timestamp, number);
+        }
+    }
+
     let exact_constraints: Vec<u64> = constraints
         .iter()
         .filter_map(|c| match c {
             BlockConstraint::Unconstrained | BlockConstraint::NumberGTE(_) => None,
             BlockConstraint::Number(number) => Some(*number),
+            BlockConstraint::TimestampLTE(timestamp) => timestamps.get(timestamp).copied(),
             // resolving block hashes is not guaranteed
             BlockConstraint::Hash(hash) => chain
                 .find(&UnresolvedBlock::WithHash(*hash))
@@ -79,9 +105,58 @@ pub fn resolve_block_requirements(
         range: min_block.map(|min| (min, max_block.unwrap())),
         number_gte,
         latest,
+        timestamps,
     })
 }
 
+/// Returns the number of the latest block at or before the timestamp. The gateway only knows a
+/// sparse set of consensus blocks, so the block is only resolved if the block right after it is
+/// also known, and is after the timestamp.
+fn resolve_timestamp(chain: &Chain, timestamp: BlockTimestamp) -> Option<BlockNumber> {
+    let mut next: Option<&Block> = None;
+    for block in chain.consensus_blocks() {
+        if block.timestamp <= timestamp {
+            return next
+                .filter(|next| (next.number == block.number + 1) && (next.timestamp > timestamp))
+                .map(|_| block.number);
+        }
+        next = Some(block);
+    }
+    None
+}
+
+/// Returns the hashes of the blocks the query is pinned to, if the query response is deterministic.
+/// That is, if all of its block constraints are either a block hash, or a block number at least
+/// `min_confirmations` blocks below the chain head that resolves to a consensus block.
+pub fn pinned_blocks(
+    chain: &Chain,
+    context: &Context,
+    min_confirmations: u64,
+) -> Option<BTreeSet<BlockHash>> {
+    let constraints = block_constraints(context).ok()?;
+    if constraints.is_empty() {
+        return None;
+    }
+    let chain_head = chain.latest().map(|b| b.number);
+    constraints
+        .into_iter()
+        .map(|c| match c {
+            BlockConstraint::Unconstrained
+            | BlockConstraint::NumberGTE(_)
+            | BlockConstraint::TimestampLTE(_) => None,
+            BlockConstraint::Hash(hash) => Some(hash),
+            BlockConstraint::Number(number) => {
+                if number.saturating_add(min_confirmations) > chain_head? {
+                    return None;
+                }
+                chain
+                    .find(&UnresolvedBlock::WithNumber(number))
+                    .map(|b| b.hash)
+            }
+        })
+        .collect()
+}
+
 fn block_constraints(context: &Context) -> Result<BTreeSet<BlockConstraint>, Error> {
     let mut constraints = BTreeSet::new();
     let vars = &context.variables;
@@ -91,54 +166,198 @@ fn block_constraints(context: &Context) -> Result<BTreeSet<BlockConstraint>, Err
             OperationDefinition::SelectionSet(selection_set) => {
                 (selection_set, BTreeMap::default())
             }
-            OperationDefinition::Query(query) if query.directives.is_empty() => {
-                // Add default definitions for variables not set at top level.
-                let defaults: BTreeMap<String, StaticValue> = query
-                    .variable_definitions
-                    .iter()
-                    .filter(|d| !vars.0.contains_key(d.name))
-                    .filter_map(|d| {
-                        Some((d.name.to_string(), d.default_value.as_ref()?.to_graphql()))
-                    })
-                    .collect();
-                (&query.selection_set, defaults)
+            OperationDefinition::Query(query) => {
+                (&query.selection_set, variable_defaults(vars, query))
             }
-            OperationDefinition::Query(_)
-            | OperationDefinition::Mutation(_)
-            | OperationDefinition::Subscription(_) => {
+            OperationDefinition::Mutation(_) | OperationDefinition::Subscription(_) => {
                 return Err(Error::BadQuery(anyhow!("unsupported GraphQL features")))
             }
         };
-        for selection in &selection_set.items {
-            let selection_field = match selection {
-                Selection::Field(field) => field,
-                Selection::FragmentSpread(_) | Selection::InlineFragment(_) => {
-                    return Err(Error::BadQuery(anyhow!("unsupported GraphQL features")))
-                }
-            };
-            let constraint = match selection_field
-                .arguments
-                .iter()
-                .find(|(k, _)| *k == "block")
+        let mut fragments = BTreeMap::new();
+        collect_constraints(
+            context,
+            &defaults,
+            selection_set,
+            &mut constraints,
+            &mut fragments,
+            0,
+        )?;
+    }
+    Ok(constraints)
+}
+
+/// Default definitions for the query variables not set at top level.
+fn variable_defaults<'q>(
+    vars: &cost_model::QueryVariables,
+    query: &Query<'q, &'q str>,
+) -> BTreeMap<String, StaticValue> {
+    query
+        .variable_definitions
+        .iter()
+        .filter(|d| !vars.0.contains_key(d.name))
+        .filter_map(|d| Some((d.name.to_string(), d.default_value.as_ref()?.to_graphql())))
+        .collect()
+}
+
+/// Collect the block constraints of the top-level fields in the selection set, including the
+/// fields of top-level fragments. Selections excluded by `@skip` or `@include` are ignored.
+///
+/// Each fragment is only expanded once, since its constraints don't depend on where it's spread.
+/// `fragments` maps the names of the fragments expanded so far to whether their expansion is
+/// complete.
+fn collect_constraints<'q>(
+    context: &Context<'q>,
+    defaults: &BTreeMap<String, StaticValue>,
+    selection_set: &SelectionSet<'q, &'q str>,
+    constraints: &mut BTreeSet<BlockConstraint>,
+    fragments: &mut BTreeMap<&'q str, bool>,
+    depth: usize,
+) -> Result<(), Error> {
+    // Fragment cycles are invalid, but the query has not been validated at this point.
+    const MAX_FRAGMENT_DEPTH: usize = 8;
+    if depth > MAX_FRAGMENT_DEPTH {
+        return Err(Error::BadQuery(anyhow!("fragment nesting too deep")));
+    }
+
+    let vars = &context.variables;
+    for selection in &selection_set.items {
+        let directives = match selection {
+            Selection::Field(field) => &field.directives,
+            Selection::FragmentSpread(spread) => &spread.directives,
+            Selection::InlineFragment(fragment) => &fragment.directives,
+        };
+        if !is_included(vars, defaults, directives).map_err(Error::BadQuery)? {
+            continue;
+        }
+
+        match selection {
+            Selection::Field(field) => {
+                let constraint = match field.arguments.iter().find(|(k, _)| *k == "block") {
+                    Some((_, arg)) => {
+                        field_constraint(vars, defaults, arg).map_err(Error::BadQuery)?
+                    }
+                    None => BlockConstraint::Unconstrained,
+                };
+                constraints.insert(constraint);
+            }
+            Selection::FragmentSpread(spread) => {
+                match fragments.get(spread.fragment_name) {
+                    Some(true) => continue,
+                    Some(false) => {
+                        return Err(Error::BadQuery(anyhow!(
+                            "fragment cycle: {}",
+                            spread.fragment_name
+                        )));
+                    }
+                    None => (),
+                };
+                let fragment = context
+                    .fragments
+                    .iter()
+                    .find(|f| f.name == spread.fragment_name)
+                    .ok_or_else(|| {
+                        Error::BadQuery(anyhow!("unknown fragment: {}", spread.fragment_name))
+                    })?;
+                fragments.insert(spread.fragment_name, false);
+                collect_constraints(
+                    context,
+                    defaults,
+                    &fragment.selection_set,
+                    constraints,
+                    fragments,
+                    depth + 1,
+                )?;
+                fragments.insert(spread.fragment_name, true);
+            }
+            Selection::InlineFragment(fragment) => {
+                collect_constraints(
+                    context,
+                    defaults,
+                    &fragment.selection_set,
+                    constraints,
+                    fragments,
+                    depth + 1,
+                )?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Evaluate the `@skip` and `@include` directives of a selection.
+fn is_included<'c, T: Text<'c>>(
+    vars: &cost_model::QueryVariables,
+    defaults: &BTreeMap<String, StaticValue>,
+    directives: &[Directive<'c, T>],
+) -> anyhow::Result<bool> {
+    for directive in directives {
+        let included_if = match directive.name.as_ref() {
+            "skip" => false,
+            "include" => true,
+            _ => continue,
+        };
+        let condition = directive
+            .arguments
+            .iter()
+            .find(|(k, _)| k.as_ref() == "if")
+            .map(|(_, v)| v);
+        let condition = match condition {
+            Some(Value::Boolean(condition)) => *condition,
+            Some(Value::Variable(name)) => match vars
+                .get(name.as_ref())
+                .or_else(|| defaults.get(name.as_ref()))
             {
-                Some((_, arg)) => {
-                    field_constraint(vars, &defaults, arg).map_err(Error::BadQuery)?
-                }
-                None => BlockConstraint::Unconstrained,
-            };
-            constraints.insert(constraint);
+                Some(Value::Boolean(condition)) => *condition,
+                _ => bail!("malformed @{} condition", directive.name.as_ref()),
+            },
+            _ => bail!("malformed @{} condition", directive.name.as_ref()),
+        };
+        if condition != included_if {
+            return Ok(false);
         }
     }
-    Ok(constraints)
+    Ok(true)
 }
 
-pub fn rewrite_query<'q>(ctx: &Context<'q>) -> String {
+pub fn rewrite_query<'q>(ctx: &Context<'q>, block_requirements: &BlockRequirements) -> String {
+    let variables = serde_json::to_value(&ctx.variables).unwrap();
+    let mut fragments = Cow::Borrowed(ctx.fragments.as_slice());
+    let mut operations = Cow::Borrowed(ctx.operations.as_slice());
+    if !block_requirements.timestamps.is_empty() {
+        let defaults: BTreeMap<String, StaticValue> = ctx
+            .operations
+            .iter()
+            .flat_map(|operation| match operation {
+                OperationDefinition::Query(query) => variable_defaults(&ctx.variables, query),
+                _ => Default::default(),
+            })
+            .collect();
+        let rewrite = |selection_set: &mut SelectionSet<'q, &'q str>| {
+            rewrite_timestamp_constraints(
+                selection_set,
+                &ctx.variables,
+                &defaults,
+                &block_requirements.timestamps,
+            )
+        };
+        for fragment in fragments.to_mut() {
+            rewrite(&mut fragment.selection_set);
+        }
+        for operation in operations.to_mut() {
+            match operation {
+                OperationDefinition::SelectionSet(selection_set) => rewrite(selection_set),
+                OperationDefinition::Query(query) => rewrite(&mut query.selection_set),
+                OperationDefinition::Mutation(_) | OperationDefinition::Subscription(_) => (),
+            };
+        }
+    }
+
     let mut buf: String = Default::default();
-    for fragment in &ctx.fragments {
+    for fragment in fragments.iter() {
         write!(&mut buf, "{}", fragment).unwrap();
     }
     if contains_introspection(ctx) {
-        for operation in &ctx.operations {
+        for operation in operations.iter() {
             write!(&mut buf, "{}", operation).unwrap();
         }
     } else {
@@ -178,19 +397,65 @@ pub fn rewrite_query<'q>(ctx: &Context<'q>) -> String {
                             }
                             buf.push(')');
                         }
-                        debug_assert!(query.directives.is_empty());
+                        for directive in &query.directives {
+                            write!(buf, " {directive}").unwrap();
+                        }
                         buf.push(' ');
                         serialize_selection_set(buf, &query.selection_set);
                     }
                     OperationDefinition::Mutation(_) | OperationDefinition::Subscription(_) => (),
                 };
             };
-        for operation in &ctx.operations {
+        for operation in operations.iter() {
             serialize_operation(&mut buf, operation);
         }
     }
 
-    serde_json::to_string(&json!({ "query": buf, "variables": ctx.variables })).unwrap()
+    serde_json::to_string(&json!({ "query": buf, "variables": variables })).unwrap()
+}
+
+/// Rewrite the `timestamp_lte` block constraints of the top-level fields into `number` block
+/// constraints, using the block numbers resolved for the timestamps. The block numbers are inlined,
+/// so that variables used for the constraints keep their value for any other use in the query.
+fn rewrite_timestamp_constraints<'q>(
+    selection_set: &mut SelectionSet<'q, &'q str>,
+    vars: &cost_model::QueryVariables,
+    defaults: &BTreeMap<String, StaticValue>,
+    timestamps: &BTreeMap<BlockTimestamp, BlockNumber>,
+) {
+    for selection in &mut selection_set.items {
+        let field = match selection {
+            Selection::Field(field) => field,
+            Selection::InlineFragment(fragment) => {
+                rewrite_timestamp_constraints(
+                    &mut fragment.selection_set,
+                    vars,
+                    defaults,
+                    timestamps,
+                );
+                continue;
+            }
+            // Fragment definitions are rewritten separately.
+            Selection::FragmentSpread(_) => continue,
+        };
+        let arg = match field.arguments.iter_mut().find(|(k, _)| *k == "block") {
+            Some((_, arg)) => arg,
+            None => continue,
+        };
+        let number = match field_constraint(vars, defaults, arg) {
+            Ok(BlockConstraint::TimestampLTE(timestamp)) => match timestamps.get(&timestamp) {
+                Some(number) => *number,
+                None => continue,
+            },
+            _ => continue,
+        };
+        // Block numbers out of range are rejected when resolving the timestamps.
+        let number = match i32::try_from(number) {
+            Ok(number) => Value::Int(number.into()),
+            Err(_) => continue,
+        };
+        *arg = Value::Object(BTreeMap::from([("number", number)]));
+    }
 }
 
 fn contains_introspection(ctx: &Context<'_>) -> bool {
@@ -250,6 +515,10 @@ fn parse_constraint<'c, T: Text<'c>>(
                 n.map(BlockConstraint::NumberGTE)
                     .unwrap_or(BlockConstraint::Unconstrained)
             }),
+            ("timestamp_lte", timestamp) => parse_number(timestamp, vars, defaults).map(|t| {
+                t.map(BlockConstraint::TimestampLTE)
+                    .unwrap_or(BlockConstraint::Unconstrained)
+            }),
             _ => Err(anyhow!("unexpected block constraint: {}", k.as_ref())),
         },
     }
@@ -304,9 +573,11 @@ fn parse_number<'c, T: Text<'c>>(
 mod tests {
     use std::iter::FromIterator as _;
 
-    use alloy_primitives::hex;
+    use alloy_primitives::{hex, U256};
+    use thegraph_core::{Address, IndexerId};
 
     use super::*;
+    use crate::blocks::Block;
 
     #[test]
     fn tests() {
@@ -353,6 +624,54 @@ mod tests {
                 "query($b: Block_height = {number_gte:0}) { a(block:$b) }",
                 Ok(vec![NumberGTE(0)]),
             ),
+            (
+                "query q @foo { a(block:{number:1}) }",
+                Ok(vec![Number(1)]),
+            ),
+            (
+                "{ ...f } fragment f on Query { a(block:{number:1}) b }",
+                Ok(vec![Number(1), Unconstrained]),
+            ),
+            (
+                "{ ...f } fragment f on Query { ...g } fragment g on Query { a(block:{number:1}) }",
+                Ok(vec![Number(1)]),
+            ),
+            (
+                "{ ...f }",
+                Err("bad query: unknown fragment: f"),
+            ),
+            (
+                "{ ...f } fragment f on Query { ...f }",
+                Err("bad query: fragment cycle: f"),
+            ),
+            (
+                "{ ...f } fragment f on Query { ...g ...g a } fragment g on Query { ...f }",
+                Err("bad query: fragment cycle: f"),
+            ),
+            (
+                "{ ...f ...g } fragment f on Query { ...g b } fragment g on Query { a(block:{number:1}) }",
+                Ok(vec![Number(1), Unconstrained]),
+            ),
+            (
+                "{ ... on Query { a(block:{number:1}) } b(block:{number:2}) }",
+                Ok(vec![Number(1), Number(2)]),
+            ),
+            (
+                "{ a(block:{number:1}) @skip(if:true) b(block:{number:2}) }",
+                Ok(vec![Number(2)]),
+            ),
+            (
+                "query($s: Boolean = false) { a(block:{number:1}) @include(if:$s) ...f @include(if:true) } fragment f on Query { b(block:{number:2}) }",
+                Ok(vec![Number(2)]),
+            ),
+            (
+                "{ a(block:{timestamp_lte:10}) }",
+                Ok(vec![TimestampLTE(10)]),
+            ),
+            (
+                "{ a(block:{number:1}) @skip(if:1) }",
+                Err("bad query: malformed @skip condition"),
+            ),
         ];
         for (query, expected) in tests {
             let context = Context::new(query, "").unwrap();
@@ -364,6 +683,146 @@ mod tests {
         }
     }
 
+    #[test]
+    fn fragments_are_expanded_once() {
+        //* Given
+        // Without expanding each fragment once, this would expand the last fragment 16^7 times.
+        let mut query = "{ ...f0 }".to_string();
+        for i in 0..7 {
+            let spreads = format!("...f{} ", i + 1).repeat(16);
+            query.push_str(&format!(" fragment f{i} on Query {{ {spreads} }}"));
+        }
+        query.push_str(" fragment f7 on Query { a(block:{number:1}) }");
+        let context = Context::new(&query, "").unwrap();
+
+        //* When
+        let constraints = block_constraints(&context);
+
+        //* Then
+        assert_eq!(
+            constraints.unwrap(),
+            BTreeSet::from_iter([BlockConstraint::Number(1)])
+        );
+    }
+
+    #[test]
+    fn pinned_blocks_require_confirmed_exact_constraints() {
+        //* Given
+        let mut chain = Chain::default();
+        for number in 90..=100 {
+            let block = Block {
+                number,
+                hash: BlockHash::from(U256::from(number)),
+                timestamp: number,
+            };
+            chain.insert(block, IndexerId::from(Address::ZERO).into());
+        }
+        let hash: BlockHash =
+            hex!("0000000000000000000000000000000000000000000000000000000000054321").into();
+
+        let pinned = |query: &str| {
+            let context = Context::new(query, "").unwrap();
+            pinned_blocks(&chain, &context, 5)
+        };
+
+        //* Then
+        assert_eq!(
+            pinned(&format!("{{ a(block:{{hash:{:?}}}) }}", hash.to_string())),
+            Some([hash].into())
+        );
+        assert_eq!(
+            pinned("{ a(block:{number:92}) b(block:{number:95}) }"),
+            Some(
+                [
+                    BlockHash::from(U256::from(92)),
+                    BlockHash::from(U256::from(95)),
+                ]
+                .into()
+            )
+        );
+        // too close to chain head
+        assert_eq!(pinned("{ a(block:{number:96}) }"), None);
+        // not a consensus block
+        assert_eq!(pinned("{ a(block:{number:10}) }"), None);
+        assert_eq!(pinned("{ a(block:{number:92}) b }"), None);
+        assert_eq!(pinned("{ a(block:{number_gte:92}) }"), None);
+    }
+
+    #[test]
+    fn resolve_and_rewrite_timestamp_constraints() {
+        //* Given
+        let mut chain = Chain::default();
+        for number in 90..=100 {
+            let block = Block {
+                number,
+                hash: BlockHash::from(U256::from(number)),
+                timestamp: number * 12,
+            };
+            chain.insert(block, IndexerId::from(Address::ZERO).into());
+        }
+        let query = "query($t: Int) { a(block:{timestamp_lte:$t}, where:{t:$t}) { id } b(block:{timestamp_lte:1150}) { id } }";
+        let context = Context::new(query, r#"{"t":1100}"#).unwrap();
+
+        //* When
+        let requirements = resolve_block_requirements(&chain, &context, 0).unwrap();
+        let rewritten: serde_json::Value =
+            serde_json::from_str(&rewrite_query(&context, &requirements)).unwrap();
+
+        //* Then
+        assert_eq!(requirements.range, Some((91, 95)));
+        assert!(!requirements.latest);
+        // The variable keeps its value for other uses
+        assert_eq!(rewritten["variables"], json!({ "t": 1100 }));
+        let variables = rewritten["variables"].to_string();
+        let rewritten_context =
+            Context::new(rewritten["query"].as_str().unwrap(), &variables).unwrap();
+        assert_eq!(
+            block_constraints(&rewritten_context).unwrap(),
+            BTreeSet::from_iter([
+                BlockConstraint::Number(91),
+                BlockConstraint::Number(95),
+                // gateway probe
+                BlockConstraint::Unconstrained,
+            ])
+        );
+
+        // timestamps outside of the known chain history, or at the latest block
+        for timestamp in [1000, 1200, 1300] {
+            let query = format!("{{ a(block:{{timestamp_lte:{timestamp}}}) }}");
+            let context = Context::new(&query, "").unwrap();
+            assert!(matches!(
+                resolve_block_requirements(&chain, &context, 0),
+                Err(Error::BlockNotFound(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn timestamps_are_not_resolved_across_gaps() {
+        //* Given
+        let mut chain = Chain::default();
+        for number in (90..=100).filter(|n| *n != 96) {
+            let block = Block {
+                number,
+                hash: BlockHash::from(U256::from(number)),
+                timestamp: number * 12,
+            };
+            chain.insert(block, IndexerId::from(Address::ZERO).into());
+        }
+        let resolve = |timestamp: u64| {
+            let query = format!("{{ a(block:{{timestamp_lte:{timestamp}}}) }}");
+            let context = Context::new(&query, "").unwrap();
+            resolve_block_requirements(&chain, &context, 0).map(|r| r.range)
+        };
+
+        //* Then
+        assert_eq!(resolve(1139).unwrap(), Some((94, 94)));
+        // Block 95 is the latest known block before the timestamp, but block 96 is unknown.
+        assert!(matches!(resolve(1150), Err(Error::BlockNotFound(_))));
+        assert!(matches!(resolve(1160), Err(Error::BlockNotFound(_))));
+        assert_eq!(resolve(1170).unwrap(), Some((97, 97)));
+    }
+
     #[test]
     fn query_contains_introspection() {
         let examples = [
@@ -14,6 +14,8 @@ pub struct Block {
 pub enum UnresolvedBlock {
     WithHash(BlockHash),
     WithNumber(BlockNumber),
+    /// The latest block with a timestamp less than or equal to the given timestamp
+    WithTimestampLTE(BlockTimestamp),
 }
 
 impl UnresolvedBlock {
@@ -21,6 +23,7 @@ impl UnresolvedBlock {
         match self {
             Self::WithHash(hash) => hash == &block.hash,
             Self::WithNumber(number) => number == &block.number,
+            Self::WithTimestampLTE(timestamp) => block.timestamp <= *timestamp,
         }
     }
 }
@@ -30,6 +33,7 @@ impl fmt::Display for UnresolvedBlock {
         match self {
             Self::WithHash(hash) => write!(f, "{hash}"),
             Self::WithNumber(number) => write!(f, "{number}"),
+            Self::WithTimestampLTE(timestamp) => write!(f, "timestamp_lte {timestamp}"),
         }
     }
 }
@@ -40,6 +44,7 @@ pub enum BlockConstraint {
     Hash(BlockHash),
     Number(BlockNumber),
     NumberGTE(BlockNumber),
+    TimestampLTE(BlockTimestamp),
 }
 
 impl BlockConstraint {
@@ -48,6 +53,7 @@ impl BlockConstraint {
             Self::Unconstrained => None,
             Self::Hash(h) => Some(UnresolvedBlock::WithHash(h)),
             Self::Number(n) | Self::NumberGTE(n) => Some(UnresolvedBlock::WithNumber(n)),
+            Self::TimestampLTE(t) => Some(UnresolvedBlock::WithTimestampLTE(t)),
         }
     }
 }
@@ -14,15 +14,16 @@ pub struct USD(pub NotNan<f64>);
 
 pub struct Budgeter {
     pub feedback: mpsc::UnboundedSender<USD>,
-    pub query_fees_target: USD,
+    pub query_fees_target: watch::Receiver<USD>,
     pub min_indexer_fees: watch::Receiver<USD>,
 }
 
 impl Budgeter {
-    pub fn new(query_fees_target: USD) -> Self {
+    pub fn new(query_fees_target: watch::Receiver<USD>) -> Self {
         let (feedback_tx, feedback_rx) = mpsc::unbounded_channel();
-        let (min_indexer_fees_tx, min_indexer_fees_rx) = watch::channel(query_fees_target);
-        Actor::create(feedback_rx, min_indexer_fees_tx, query_fees_target);
+        let (min_indexer_fees_tx, min_indexer_fees_rx) =
+            watch::channel(*query_fees_target.borrow());
+        Actor::create(feedback_rx, min_indexer_fees_tx, query_fees_target.clone());
         Self {
             feedback: feedback_tx,
             query_fees_target,
@@ -33,6 +34,7 @@ impl Budgeter {
 
 struct Actor {
     feedback: mpsc::UnboundedReceiver<USD>,
+    query_fees_target: watch::Receiver<USD>,
     min_indexer_fees: watch::Sender<USD>,
     controller: Controller,
 }
@@ -41,12 +43,14 @@ impl Actor {
     fn create(
         feedback: mpsc::UnboundedReceiver<USD>,
         min_indexer_fees: watch::Sender<USD>,
-        query_fees_target: USD,
+        mut query_fees_target: watch::Receiver<USD>,
     ) {
+        let controller = Controller::new(*query_fees_target.borrow_and_update());
         let mut actor = Actor {
             feedback,
+            query_fees_target,
             min_indexer_fees,
-            controller: Controller::new(query_fees_target),
+            controller,
         };
         let mut budget_timer = interval(Duration::from_secs(1));
         budget_timer.set_missed_tick_behavior(MissedTickBehavior::Skip);
@@ -54,6 +58,7 @@ impl Actor {
             loop {
                 select! {
                     Some(msg) = actor.feedback.recv() => actor.feedback(msg),
+                    Ok(()) = actor.query_fees_target.changed() => actor.update_target(),
                     _ = budget_timer.tick() => actor.revise_budget(),
                 }
             }
@@ -64,6 +69,12 @@ impl Actor {
         self.controller.add_recent_fees(fees);
     }
 
+    fn update_target(&mut self) {
+        let query_fees_target = *self.query_fees_target.borrow_and_update();
+        tracing::info!(query_fees_target = *query_fees_target.0);
+        self.controller.query_fees_target = query_fees_target;
+    }
+
     fn revise_budget(&mut self) {
         if self.controller.recent_count == 0 {
             return;
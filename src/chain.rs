@@ -1,17 +1,69 @@
 use std::{
-    collections::{BTreeMap, BTreeSet},
+    collections::{BTreeMap, BTreeSet, VecDeque},
     iter,
 };
 
-use thegraph_core::IndexerId;
+use serde::Serialize;
+use thegraph_core::{BlockHash, BlockNumber, IndexerId};
 
-use crate::blocks::{Block, UnresolvedBlock};
+use crate::{
+    blocks::{Block, UnresolvedBlock},
+    time::unix_timestamp,
+};
+
+/// The source of a block reported to the chain.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum BlockSource {
+    /// Block from an indexer response (`_gateway_probe_`)
+    Indexer(IndexerId),
+    /// Block polled from the chain's configured RPC provider
+    Rpc,
+}
+
+impl BlockSource {
+    /// The weight of the source when selecting consensus blocks.
+    fn weight(&self) -> usize {
+        match self {
+            Self::Indexer(_) => 1,
+            Self::Rpc => RPC_WEIGHT,
+        }
+    }
+}
+
+impl From<IndexerId> for BlockSource {
+    fn from(indexer: IndexerId) -> Self {
+        Self::Indexer(indexer)
+    }
+}
+
+/// A change of the consensus block at some block numbers, orphaning the previous consensus blocks.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Reorg {
+    /// Milliseconds since Unix epoch, of when the reorg was detected
+    pub detected_at: u64,
+    /// The lowest block number where the consensus block changed
+    pub number: BlockNumber,
+    /// The number of orphaned blocks
+    pub depth: u64,
+    /// The hashes of the orphaned blocks
+    pub orphaned: Vec<BlockHash>,
+}
 
 #[derive(Default)]
-pub struct Chain(BTreeMap<Block, BTreeSet<IndexerId>>);
+pub struct Chain {
+    blocks: BTreeMap<Block, BTreeSet<BlockSource>>,
+    /// The consensus block hashes, as of the last reorg detection
+    consensus: BTreeMap<BlockNumber, BlockHash>,
+    /// Recent reorgs, starting from the oldest
+    reorgs: VecDeque<Reorg>,
+}
 
 const MAX_LEN: usize = 512;
+const MAX_REORGS: usize = 32;
 const DEFAULT_BLOCKS_PER_MINUTE: u64 = 6;
+/// The RPC provider is configured by the gateway operator, so its blocks outweigh a small number of
+/// indexers reporting a different block with the same number.
+const RPC_WEIGHT: usize = 3;
 
 impl Chain {
     pub fn latest(&self) -> Option<&Block> {
@@ -38,33 +90,80 @@ impl Chain {
         (bps * 60.0) as u64
     }
 
-    pub fn should_insert(&self, block: &Block, indexer: &IndexerId) -> bool {
+    pub fn should_insert(&self, block: &Block, source: &BlockSource) -> bool {
         let redundant = self
-            .0
+            .blocks
             .get(block)
-            .map(|indexers| indexers.contains(indexer))
+            .map(|sources| sources.contains(source))
             .unwrap_or(false);
-        let lowest_block = self.0.first_key_value().map(|(b, _)| b.number).unwrap_or(0);
-        let has_space = (self.0.len() < MAX_LEN) || (block.number > lowest_block);
+        let lowest_block = self
+            .blocks
+            .first_key_value()
+            .map(|(b, _)| b.number)
+            .unwrap_or(0);
+        let has_space = (self.blocks.len() < MAX_LEN) || (block.number > lowest_block);
         !redundant && has_space
     }
 
-    pub fn insert(&mut self, block: Block, indexer: IndexerId) {
-        tracing::trace!(%indexer, ?block);
-        debug_assert!(self.should_insert(&block, &indexer));
-        if self.0.len() >= MAX_LEN {
+    pub fn insert(&mut self, block: Block, source: BlockSource) {
+        tracing::trace!(?source, ?block);
+        debug_assert!(self.should_insert(&block, &source));
+        if self.blocks.len() >= MAX_LEN {
             self.evict();
         }
-        self.0.entry(block).or_default().insert(indexer);
+        self.blocks.entry(block).or_default().insert(source);
+    }
+
+    /// Recent reorgs, starting from the oldest.
+    pub fn reorgs(&self) -> impl Iterator<Item = &Reorg> {
+        self.reorgs.iter()
+    }
+
+    /// Compare the consensus blocks to the ones at the last call, and return a reorg if the
+    /// consensus block changed at some block numbers. The orphaned blocks are removed, so their
+    /// sources don't count towards the consensus if the block is reported again.
+    pub fn detect_reorg(&mut self) -> Option<Reorg> {
+        let consensus = self.consensus_hashes();
+        let orphaned: BTreeMap<BlockNumber, BlockHash> = self
+            .consensus
+            .iter()
+            .filter(|(number, hash)| consensus.get(number).is_some_and(|h| h != *hash))
+            .map(|(number, hash)| (*number, *hash))
+            .collect();
+        if orphaned.is_empty() {
+            self.consensus = consensus;
+            return None;
+        }
+
+        self.blocks
+            .retain(|block, _| orphaned.get(&block.number) != Some(&block.hash));
+        self.consensus = self.consensus_hashes();
+        let reorg = Reorg {
+            detected_at: unix_timestamp(),
+            number: *orphaned.keys().next().unwrap(),
+            depth: orphaned.len() as u64,
+            orphaned: orphaned.into_values().collect(),
+        };
+        if self.reorgs.len() >= MAX_REORGS {
+            self.reorgs.pop_front();
+        }
+        self.reorgs.push_back(reorg.clone());
+        Some(reorg)
+    }
+
+    fn consensus_hashes(&self) -> BTreeMap<BlockNumber, BlockHash> {
+        self.consensus_blocks()
+            .map(|block| (block.number, block.hash))
+            .collect()
     }
 
     /// Remove all entries associated with the lowest block number.
     fn evict(&mut self) {
-        let min_block = match self.0.pop_first() {
+        let min_block = match self.blocks.pop_first() {
             Some((min_block, _)) => min_block,
             None => return,
         };
-        while let Some(entry) = self.0.first_entry() {
+        while let Some(entry) = self.blocks.first_entry() {
             debug_assert!(entry.key().number >= min_block.number);
             if entry.key().number > min_block.number {
                 break;
@@ -73,14 +172,15 @@ impl Chain {
         }
     }
 
-    /// Return blocks with simple majority consensus, starting from the latest block.
+    /// Return blocks with simple majority consensus, starting from the latest block. Sources are
+    /// weighted, see [`BlockSource`].
     pub fn consensus_blocks(&self) -> impl Iterator<Item = &Block> {
         struct ConsensusBlocks<Iter> {
             blocks: Iter,
         }
         impl<'c, Iter> Iterator for ConsensusBlocks<iter::Peekable<Iter>>
         where
-            Iter: Iterator<Item = (&'c Block, &'c BTreeSet<IndexerId>)> + Clone,
+            Iter: Iterator<Item = (&'c Block, &'c BTreeSet<BlockSource>)> + Clone,
         {
             type Item = &'c Block;
             fn next(&mut self) -> Option<Self::Item> {
@@ -88,8 +188,8 @@ impl Chain {
                     let number = self.blocks.peek()?.0.number;
                     let forks = self.blocks.clone().take_while(|(b, _)| b.number == number);
                     let forks_len = forks.clone().count();
-                    let max_indexers = forks.clone().map(|(_, i)| i.len()).max().unwrap();
-                    let mut candidates = forks.clone().filter(|(_, i)| i.len() == max_indexers);
+                    let max_weight = forks.clone().map(|(_, s)| weight(s)).max().unwrap();
+                    let mut candidates = forks.clone().filter(|(_, s)| weight(s) == max_weight);
                     for _ in 0..forks_len {
                         self.blocks.next();
                     }
@@ -100,11 +200,15 @@ impl Chain {
             }
         }
         ConsensusBlocks {
-            blocks: self.0.iter().rev().peekable(),
+            blocks: self.blocks.iter().rev().peekable(),
         }
     }
 }
 
+fn weight(sources: &BTreeSet<BlockSource>) -> usize {
+    sources.iter().map(BlockSource::weight).sum()
+}
+
 #[cfg(test)]
 mod tests {
     use alloy_primitives::U256;
@@ -115,7 +219,7 @@ mod tests {
     use thegraph_core::{Address, BlockHash, IndexerId};
     use toolshed::concat_bytes;
 
-    use super::{Block, Chain, MAX_LEN};
+    use super::{Block, BlockSource, Chain, UnresolvedBlock, MAX_LEN};
 
     #[test]
     fn chain() {
@@ -137,34 +241,101 @@ mod tests {
                 timestamp,
             };
             let indexer = *indexers.choose(&mut rng).unwrap();
+            let indexer = BlockSource::Indexer(indexer);
             if chain.should_insert(&block, &indexer) {
                 chain.insert(block, indexer);
             }
         }
 
-        // println!("{:#?}", chain.0);
+        // println!("{:#?}", chain.blocks);
         // println!("{:#?}", chain.consensus_blocks().collect::<Vec<_>>());
 
-        assert!(chain.0.len() <= MAX_LEN, "chain len above max");
-        assert!(chain.consensus_blocks().count() <= chain.0.len());
+        assert!(chain.blocks.len() <= MAX_LEN, "chain len above max");
+        assert!(chain.consensus_blocks().count() <= chain.blocks.len());
         assert!(chain.blocks_per_minute() > 0);
-        let blocks = || chain.0.keys();
+        let blocks = || chain.blocks.keys();
         assert!(
             blocks().tuple_windows().all(|(a, b)| a.number <= b.number),
             "chain block numbers not monotonic, check ord impl"
         );
         for block in chain.consensus_blocks() {
             let max_fork_indexers = chain
-                .0
+                .blocks
                 .iter()
                 .filter(|(block, _)| (block != block) && (block.number == block.number))
-                .map(|(_, indexers)| indexers.len())
+                .map(|(_, sources)| super::weight(sources))
                 .max()
                 .unwrap_or(0);
             assert!(
-                chain.0.get(block).unwrap().len() > max_fork_indexers,
+                super::weight(chain.blocks.get(block).unwrap()) > max_fork_indexers,
                 "consensus block without majority consensus"
             );
         }
     }
+
+    #[test]
+    fn rpc_blocks_outweigh_indexers() {
+        //* Given
+        let mut chain: Chain = Default::default();
+        let indexers: Vec<BlockSource> = (1..=2)
+            .map(|n| {
+                BlockSource::Indexer(Address::from(concat_bytes!(20, [&[0; 19], &[n]])).into())
+            })
+            .collect();
+        let block = |hash: u64| Block {
+            number: 1,
+            hash: BlockHash::from(U256::from(hash)),
+            timestamp: 1,
+        };
+
+        //* When
+        for indexer in &indexers {
+            chain.insert(block(1), *indexer);
+        }
+        chain.insert(block(2), BlockSource::Rpc);
+
+        //* Then
+        assert_eq!(chain.latest(), Some(&block(2)));
+    }
+
+    #[test]
+    fn reorg_detection() {
+        //* Given
+        let mut chain: Chain = Default::default();
+        let indexer = BlockSource::Indexer(Address::ZERO.into());
+        let block = |number: u64, hash: u64| Block {
+            number,
+            hash: BlockHash::from(U256::from(hash)),
+            timestamp: number,
+        };
+        for number in 1..=3 {
+            chain.insert(block(number, number), indexer);
+        }
+        assert_eq!(chain.detect_reorg(), None);
+
+        //* When
+        chain.insert(block(2, 20), BlockSource::Rpc);
+        chain.insert(block(3, 30), BlockSource::Rpc);
+        let reorg = chain.detect_reorg().expect("reorg not detected");
+
+        //* Then
+        assert_eq!(reorg.number, 2);
+        assert_eq!(reorg.depth, 2);
+        assert_eq!(reorg.orphaned, vec![block(2, 2).hash, block(3, 3).hash]);
+        assert_eq!(chain.latest(), Some(&block(3, 30)));
+        let orphaned = UnresolvedBlock::WithHash(block(2, 2).hash);
+        assert_eq!(chain.find(&orphaned), None);
+        assert_eq!(chain.detect_reorg(), None);
+        assert_eq!(chain.reorgs().count(), 1);
+
+        // Orphaned blocks become the consensus again once they outweigh the other blocks
+        assert!(chain.should_insert(&block(3, 3), &indexer));
+        chain.insert(block(3, 3), indexer);
+        for indexer in (1..=3).map(|i| BlockSource::Indexer(Address::with_last_byte(i).into())) {
+            chain.insert(block(3, 3), indexer);
+        }
+        assert_eq!(chain.latest(), Some(&block(3, 3)));
+        let reorg = chain.detect_reorg().expect("reorg not detected");
+        assert_eq!(reorg.orphaned, vec![block(3, 30).hash]);
+    }
 }
@@ -4,14 +4,19 @@ use std::{
 };
 
 use parking_lot::{RwLock, RwLockReadGuard};
-use thegraph_core::IndexerId;
 use tokio::{
     select, spawn,
-    sync::mpsc,
+    sync::{mpsc, watch},
     time::{interval, MissedTickBehavior},
 };
 
-use crate::{blocks::Block, chain::Chain, metrics::METRICS};
+use crate::{
+    blocks::Block,
+    chain::{BlockSource, Chain},
+    metrics::METRICS,
+};
+
+pub mod head_poller;
 
 #[derive(Clone)]
 pub struct ChainReader {
@@ -24,18 +29,18 @@ impl ChainReader {
         self.chain.read()
     }
 
-    pub fn notify(&self, block: Block, indexer: IndexerId) {
-        let _ = self.tx.send(Msg { block, indexer });
+    pub fn notify(&self, block: Block, source: BlockSource) {
+        let _ = self.tx.send(Msg { block, source });
     }
 }
 
 pub struct Chains {
     data: RwLock<HashMap<String, ChainReader>>,
-    aliases: BTreeMap<String, String>,
+    aliases: watch::Receiver<BTreeMap<String, String>>,
 }
 
 impl Chains {
-    pub fn new(aliases: BTreeMap<String, String>) -> Self {
+    pub fn new(aliases: watch::Receiver<BTreeMap<String, String>>) -> Self {
         Self {
             data: Default::default(),
             aliases,
@@ -43,7 +48,9 @@ impl Chains {
     }
 
     pub fn chain(&self, name: &str) -> ChainReader {
-        let name = self.aliases.get(name).map(|a| a.as_str()).unwrap_or(name);
+        // Note: holding watch::Ref for the rest of the function
+        let aliases = self.aliases.borrow();
+        let name = aliases.get(name).map(|a| a.as_str()).unwrap_or(name);
         {
             let reader = self.data.read();
             if let Some(chain) = reader.get(name) {
@@ -58,11 +65,18 @@ impl Chains {
                 .clone()
         }
     }
+
+    /// Return the chain, only if it is already tracked.
+    pub fn get(&self, name: &str) -> Option<ChainReader> {
+        let aliases = self.aliases.borrow();
+        let name = aliases.get(name).map(|a| a.as_str()).unwrap_or(name);
+        self.data.read().get(name).cloned()
+    }
 }
 
 struct Msg {
     block: Block,
-    indexer: IndexerId,
+    source: BlockSource,
 }
 
 struct Actor;
@@ -77,7 +91,7 @@ impl Actor {
             timer.set_missed_tick_behavior(MissedTickBehavior::Delay);
             loop {
                 select! {
-                    _ = rx.recv_many(&mut msgs, 32) => Self::handle_msgs(chain, &mut msgs),
+                    _ = rx.recv_many(&mut msgs, 32) => Self::handle_msgs(&chain_name, chain, &mut msgs),
                     _ = timer.tick() => {
                         let blocks_per_minute = chain.read().blocks_per_minute();
                         METRICS
@@ -91,18 +105,32 @@ impl Actor {
         ChainReader { tx, chain }
     }
 
-    fn handle_msgs(chain: &RwLock<Chain>, msgs: &mut Vec<Msg>) {
+    fn handle_msgs(chain_name: &str, chain: &RwLock<Chain>, msgs: &mut Vec<Msg>) {
         {
             let reader = chain.read();
-            msgs.retain(|Msg { block, indexer }| reader.should_insert(block, indexer));
+            msgs.retain(|Msg { block, source }| reader.should_insert(block, source));
         }
         {
             let mut writer = chain.write();
-            for Msg { block, indexer } in msgs.drain(..) {
-                if writer.should_insert(&block, &indexer) {
-                    writer.insert(block, indexer);
+            for Msg { block, source } in msgs.drain(..) {
+                if writer.should_insert(&block, &source) {
+                    writer.insert(block, source);
                 }
             }
+            if let Some(reorg) = writer.detect_reorg() {
+                tracing::warn!(
+                    chain = chain_name,
+                    number = reorg.number,
+                    depth = reorg.depth,
+                    orphaned = ?reorg.orphaned,
+                    "chain reorg"
+                );
+                METRICS.chain_reorgs.with_label_values(&[chain_name]).inc();
+                METRICS
+                    .chain_reorg_depth
+                    .with_label_values(&[chain_name])
+                    .observe(reorg.depth as f64);
+            }
         }
         debug_assert!(msgs.is_empty());
     }
@@ -0,0 +1,75 @@
+//! Chain head poller.
+//!
+//! Indexer responses only report the chain head for chains with query traffic. The poller fetches
+//! the latest block from a JSON-RPC provider (`eth_getBlockByNumber`), so that the chain head of
+//! quiet chains is also kept up to date.
+
+use std::time::Duration;
+
+use alloy_primitives::U64;
+use anyhow::{anyhow, Context as _};
+use serde::Deserialize;
+use serde_json::json;
+use thegraph_core::BlockHash;
+use tokio::time::{interval, MissedTickBehavior};
+use url::Url;
+
+use super::ChainReader;
+use crate::{blocks::Block, chain::BlockSource};
+
+/// Spawn a task polling the latest block from the RPC provider, and notifying the chain of it.
+pub fn spawn(http: reqwest::Client, chain: ChainReader, rpc: Url, poll_interval: Duration) {
+    tokio::spawn(async move {
+        let mut interval = interval(poll_interval);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            match fetch_latest_block(&http, &rpc).await {
+                Ok(block) => chain.notify(block, BlockSource::Rpc),
+                Err(rpc_block_err) => {
+                    tracing::warn!(rpc = %rpc.host_str().unwrap_or(""), %rpc_block_err);
+                }
+            };
+        }
+    });
+}
+
+#[derive(Deserialize)]
+struct RpcResponse {
+    result: Option<RpcBlock>,
+    error: Option<serde_json::Value>,
+}
+
+#[derive(Deserialize)]
+struct RpcBlock {
+    number: U64,
+    hash: BlockHash,
+    timestamp: U64,
+}
+
+async fn fetch_latest_block(http: &reqwest::Client, rpc: &Url) -> anyhow::Result<Block> {
+    let request = json!({
+        "jsonrpc": "2.0",
+        "id": 1,
+        "method": "eth_getBlockByNumber",
+        "params": ["latest", false],
+    });
+    let response: RpcResponse = http
+        .post(rpc.clone())
+        .json(&request)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())?
+        .json()
+        .await
+        .context("malformed RPC response")?;
+    if let Some(err) = response.error {
+        return Err(anyhow!("RPC error: {err}"));
+    }
+    let block = response.result.context("missing latest block")?;
+    Ok(Block {
+        number: block.number.to(),
+        hash: block.hash,
+        timestamp: block.timestamp.to(),
+    })
+}
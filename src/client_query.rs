@@ -8,7 +8,7 @@ use anyhow::anyhow;
 use axum::{
     body::Bytes,
     extract::{Path, State},
-    http::{Response, StatusCode},
+    http::{HeaderName, Response, StatusCode},
     Extension,
 };
 use cost_model::{Context as AgoraContext, CostModel};
@@ -26,14 +26,22 @@ use tokio::sync::mpsc;
 use tracing::{info_span, Instrument as _};
 use url::Url;
 
+pub use self::dry_run::handle_dry_run;
 use self::{
-    attestation_header::GraphAttestation, context::Context, query_selector::QuerySelector,
+    attestation_header::GraphAttestation,
+    context::Context,
+    query_selector::QuerySelector,
     query_settings::QuerySettings,
+    response_cache::{CacheKey, ResponseCache},
 };
 use crate::{
     auth::AuthSettings,
-    block_constraints::{resolve_block_requirements, rewrite_query, BlockRequirements},
+    block_constraints::{
+        pinned_blocks, resolve_block_requirements, rewrite_query, BlockRequirements,
+    },
     budgets::USD,
+    chain::{BlockSource, Chain},
+    config::SelectionParams,
     errors::{Error, IndexerError, IndexerErrors, MissingBlockError, UnavailableReason},
     http_ext::HttpBuilderExt as _,
     indexer_client::{IndexerAuth, IndexerResponse},
@@ -47,12 +55,31 @@ use crate::{
 };
 
 mod attestation_header;
+pub mod circuit_breaker;
 pub mod context;
+mod dry_run;
+pub mod health_prober;
 mod query_selector;
-mod query_settings;
+pub mod query_settings;
+pub mod response_cache;
 
 const SELECTION_LIMIT: usize = 3;
 
+/// Response header with the number of the block the indexer response was served from.
+static GRAPH_BLOCK_NUMBER: HeaderName = HeaderName::from_static("graph-block-number");
+/// Response header with the hash of the block the indexer response was served from.
+static GRAPH_BLOCK_HASH: HeaderName = HeaderName::from_static("graph-block-hash");
+/// Response header with the timestamp of the block the indexer response was served from.
+static GRAPH_BLOCK_TIMESTAMP: HeaderName = HeaderName::from_static("graph-block-timestamp");
+/// Response header with the ID of the indexer that served the response.
+static GRAPH_INDEXER: HeaderName = HeaderName::from_static("graph-indexer");
+/// Response header with the deployment that served the response.
+static GRAPH_DEPLOYMENT: HeaderName = HeaderName::from_static("graph-deployment");
+/// Response header with the fee paid to the indexer that served the response, in GRT wei.
+static GRAPH_FEE_GRT_WEI: HeaderName = HeaderName::from_static("graph-fee-grt-wei");
+/// Response header set when the response was served from the response cache.
+static GRAPH_CACHED: HeaderName = HeaderName::from_static("graph-cached");
+
 #[derive(Debug, Deserialize)]
 pub struct QueryBody {
     pub query: String,
@@ -76,25 +103,38 @@ pub async fn handle_query(
     let client_request: QueryBody =
         serde_json::from_reader(payload.reader()).map_err(|err| Error::BadQuery(err.into()))?;
 
-    // Calculate the budget for the query
-    let grt_per_usd = *ctx.grt_per_usd.borrow();
-    let one_grt = NotNan::new(1e18).unwrap();
-    let budget = {
-        let mut budget = *(ctx.budgeter.query_fees_target.0 * grt_per_usd * one_grt) as u128;
-        if let Some(Extension(QuerySettings {
-            budget_usd: Some(user_budget_usd),
-        })) = query_settings
-        {
-            // Security: Consumers can and will set their budget to unreasonably high values.
-            // This `.min` prevents the budget from being set far beyond what it would be
-            // automatically. The reason this is important is that sometimes queries are
-            // subsidized, and we would be at-risk to allow arbitrarily high values.
-            let max_budget = budget * 10;
+    let query_settings = query_settings
+        .map(|Extension(settings)| settings)
+        .unwrap_or_default();
 
-            budget = (*(user_budget_usd * grt_per_usd * one_grt) as u128).min(max_budget);
-        }
-        budget
-    };
+    // Serve block-pinned queries from the response cache, if possible.
+    let cache_key = ctx
+        .response_cache
+        .and_then(|cache| response_cache_key(&ctx, cache, &subgraph, &client_request));
+    if let Some(served) = cache_key
+        .as_ref()
+        .and_then(|key| ctx.response_cache?.get(key))
+        .filter(|served| !query_settings.excluded_indexers.contains(&served.indexer))
+    {
+        METRICS.response_cache_hits.inc();
+        METRICS.client_query.ok.inc();
+        METRICS
+            .client_query
+            .duration
+            .observe(start_time.elapsed().as_secs_f64());
+        let _ = ctx.reporter.send(reports::ClientRequest {
+            id: request_id,
+            response_time_ms: start_time.elapsed().as_millis() as u16,
+            result: Ok(()),
+            api_key: auth.key,
+            user: auth.user,
+            grt_per_usd: *ctx.grt_per_usd.borrow(),
+            indexer_requests: vec![],
+            request_bytes: client_request.query.len() as u32,
+            response_bytes: Some(served.response.client_response.len() as u32),
+        });
+        return Ok(client_response(ctx.response_metadata_headers, served, true));
+    }
 
     let (tx, mut rx) = mpsc::channel(1);
     tokio::spawn(
@@ -104,8 +144,9 @@ pub async fn handle_query(
             auth,
             start_time,
             subgraph,
-            budget,
+            query_settings,
             client_request,
+            cache_key,
             tx,
         )
         .in_current_span(),
@@ -122,20 +163,56 @@ pub async fn handle_query(
         .duration
         .observe(start_time.elapsed().as_secs_f64());
 
-    result.map(
-        |IndexerResponse {
-             client_response,
-             attestation,
-             ..
-         }| {
-            Response::builder()
-                .status(StatusCode::OK)
-                .header_typed(ContentType::json())
-                .header_typed(GraphAttestation(attestation))
-                .body(client_response)
-                .unwrap()
-        },
-    )
+    result.map(|served| client_response(ctx.response_metadata_headers, served, false))
+}
+
+/// The indexer response served to the client, and the indexing that served it.
+#[derive(Clone)]
+struct ServedResponse {
+    response: IndexerResponse,
+    indexer: IndexerId,
+    deployment: DeploymentId,
+    /// Fee paid to the indexer, in GRT wei
+    fee: u128,
+}
+
+/// Build the client response from the served indexer response. The served block number is always
+/// included. The other served block fields, the indexing, and the fee are only included when
+/// `response_metadata_headers` is enabled. Responses served from the response cache are marked as
+/// cached, and report a fee of 0, since no indexer was paid for them.
+fn client_response(
+    response_metadata_headers: bool,
+    served: ServedResponse,
+    cached: bool,
+) -> Response<String> {
+    let ServedResponse {
+        response,
+        indexer,
+        deployment,
+        fee,
+    } = served;
+    let mut builder = Response::builder()
+        .status(StatusCode::OK)
+        .header_typed(ContentType::json())
+        .header_typed(GraphAttestation(response.attestation));
+    if let Some(block) = &response.probe_block {
+        builder = builder.header(&GRAPH_BLOCK_NUMBER, block.number);
+    }
+    if response_metadata_headers {
+        if let Some(block) = &response.probe_block {
+            builder = builder
+                .header(&GRAPH_BLOCK_HASH, block.hash.to_string())
+                .header(&GRAPH_BLOCK_TIMESTAMP, block.timestamp);
+        }
+        builder = builder
+            .header(&GRAPH_INDEXER, indexer.to_string())
+            .header(&GRAPH_DEPLOYMENT, deployment.to_string())
+            .header(&GRAPH_FEE_GRT_WEI, if cached { 0 } else { fee }.to_string());
+        if cached {
+            builder = builder.header(&GRAPH_CACHED, "true");
+        }
+    }
+    builder.body(response.client_response).unwrap()
 }
 
 /// Resolve the subgraph info for the given query selector.
@@ -187,6 +264,85 @@ async fn resolve_subgraph_info(
     }
 }
 
+/// Build the response cache key for the query, if the query is pinned to specific blocks.
+fn response_cache_key(
+    ctx: &Context,
+    cache: &ResponseCache,
+    subgraph: &ResolvedSubgraphInfo,
+    client_request: &QueryBody,
+) -> Option<CacheKey> {
+    let variables = client_request
+        .variables
+        .as_ref()
+        .map(ToString::to_string)
+        .unwrap_or_default();
+    // Errors are ignored here, they are reported when the query is sent to the indexers.
+    let agora_context = AgoraContext::new(&client_request.query, &variables).ok()?;
+
+    let chain = ctx.chains.chain(&subgraph.chain);
+    let chain = chain.read();
+    let blocks = pinned_blocks(&chain, &agora_context, cache.min_confirmations)?;
+    let (chain_head, blocks_per_minute, block_requirements) =
+        resolve_chain_state(&chain, subgraph, &agora_context).ok()?;
+    let (deployment, _) = select_deployment(ctx, subgraph, chain_head, blocks_per_minute);
+
+    Some(CacheKey {
+        deployment,
+        query: rewrite_query(&agora_context, &block_requirements),
+        blocks,
+    })
+}
+
+/// Calculate the budget for the query, in GRT wei.
+fn query_budget(
+    ctx: &Context,
+    selection: &SelectionParams,
+    query_settings: &QuerySettings,
+) -> u128 {
+    let grt_per_usd = *ctx.grt_per_usd.borrow();
+    let one_grt = NotNan::new(1e18).unwrap();
+    let query_fees_target = ctx.budgeter.query_fees_target.borrow().0;
+    let mut budget = *(query_fees_target * grt_per_usd * one_grt) as u128;
+    if let Some(user_budget_usd) = query_settings.budget_usd {
+        // Security: Consumers can and will set their budget to unreasonably high values.
+        // This `.min` prevents the budget from being set far beyond what it would be
+        // automatically. The reason this is important is that sometimes queries are
+        // subsidized, and we would be at-risk to allow arbitrarily high values.
+        let max_budget = budget * selection.user_budget_max_factor as u128;
+
+        budget = (*(user_budget_usd * grt_per_usd * one_grt) as u128).min(max_budget);
+    }
+    budget
+}
+
+/// Resolve the chain head block number, the estimated blocks per minute, and the block
+/// requirements of the query, for the given subgraph.
+fn resolve_chain_state(
+    chain: &Chain,
+    subgraph: &ResolvedSubgraphInfo,
+    agora_context: &AgoraContext,
+) -> Result<(BlockNumber, u64, BlockRequirements), Error> {
+    // Get the chain head block number. Try to get it from the chain head tracker service, if it
+    // is not available, get the largest block number from the resolved indexers' indexing
+    // progress, and if that is not available, default to the subgraph start block.
+    let chain_head = chain.latest().map(|b| b.number).unwrap_or_else(|| {
+        subgraph
+            .latest_reported_block()
+            .unwrap_or(subgraph.start_block)
+    });
+
+    // Get the estimated blocks per minute for the chain
+    let blocks_per_minute = chain.blocks_per_minute();
+
+    let block_requirements = resolve_block_requirements(chain, agora_context, subgraph.start_block)
+        .map_err(|err| match err {
+            Error::BlockNotFound(_) => err,
+            _ => Error::BadQuery(anyhow!("{err}")),
+        })?;
+
+    Ok((chain_head, blocks_per_minute, block_requirements))
+}
+
 #[allow(clippy::too_many_arguments)]
 async fn run_indexer_queries(
     ctx: Context,
@@ -194,9 +350,10 @@ async fn run_indexer_queries(
     auth: AuthSettings,
     start_time: Instant,
     subgraph: ResolvedSubgraphInfo,
-    budget: u128,
+    query_settings: QuerySettings,
     client_request: QueryBody,
-    client_response: mpsc::Sender<Result<IndexerResponse, Error>>,
+    cache_key: Option<CacheKey>,
+    client_response: mpsc::Sender<Result<ServedResponse, Error>>,
 ) {
     let one_grt = NotNan::new(1e18).unwrap();
     let grt_per_usd = *ctx.grt_per_usd.borrow();
@@ -222,50 +379,41 @@ async fn run_indexer_queries(
 
     // Get the chain information for the resolved subgraph
     let chain = ctx.chains.chain(&subgraph.chain);
-    let (chain_head, blocks_per_minute, block_requirements) = {
-        let chain_reader = chain.read();
-
-        // Get the chain head block number. Try to get it from the chain head tracker service, if it
-        // is not available, get the largest block number from the resolved indexers' indexing
-        // progress, and if that is not available, default to the subgraph start block.
-        let chain_head = chain_reader.latest().map(|b| b.number).unwrap_or_else(|| {
-            subgraph
-                .latest_reported_block()
-                .unwrap_or(subgraph.start_block)
-        });
-
-        // Get the estimated blocks per minute for the chain
-        let blocks_per_minute = chain_reader.blocks_per_minute();
-
-        let block_requirements =
-            match resolve_block_requirements(&chain_reader, &agora_context, subgraph.start_block) {
-                Ok(block_requirements) => block_requirements,
-                Err(err) => {
-                    client_response
-                        .try_send(Err(Error::BadQuery(anyhow!("{err}"))))
-                        .unwrap();
-                    return;
-                }
-            };
-
-        (chain_head, blocks_per_minute, block_requirements)
-    };
+    let (chain_head, blocks_per_minute, block_requirements) =
+        match resolve_chain_state(&chain.read(), &subgraph, &agora_context) {
+            Ok(chain_state) => chain_state,
+            Err(err) => {
+                client_response.try_send(Err(err)).unwrap();
+                return;
+            }
+        };
     tracing::debug!(chain_head, blocks_per_minute, ?block_requirements);
 
+    let (deployment, selection) = select_deployment(&ctx, &subgraph, chain_head, blocks_per_minute);
+    // Calculate the budget for the query
+    let budget = query_budget(&ctx, &selection, &query_settings);
+
     let mut indexer_errors = IndexerErrors::default();
 
     // Candidate selection preparation
     let (mut candidates, errors) = build_candidates_list(
         &ctx,
+        &selection,
+        &query_settings,
         &agora_context,
         budget,
         chain_head,
         blocks_per_minute,
         &block_requirements,
-        &subgraph.versions,
+        deployment,
         subgraph.indexings,
     );
     indexer_errors.extend(errors);
+    // All candidates were filtered out by the client's freshness requirement.
+    let stale = candidates.is_empty()
+        && indexer_errors
+            .values()
+            .any(|err| matches!(err, IndexerError::Unavailable(UnavailableReason::NotFresh)));
 
     if tracing::enabled!(tracing::Level::TRACE) {
         tracing::trace!(client_query = client_request.query, variables);
@@ -277,24 +425,63 @@ async fn run_indexer_queries(
     }
 
     let client_request_bytes = client_request.query.len() as u32;
-    let indexer_query = rewrite_query(&agora_context);
+    let indexer_query = rewrite_query(&agora_context, &block_requirements);
     let mut indexer_requests: Vec<reports::IndexerRequest> = Default::default();
     let mut client_response_time: Option<Duration> = None;
     let mut client_response_bytes: Option<u32> = None;
 
     // If a client query cannot be handled by the available indexers, we should give a reason for
     // all the available indexers in the `bad indexers` response.
-    while !candidates.is_empty() && (start_time.elapsed() < Duration::from_secs(60)) {
-        let selections: ArrayVec<_, SELECTION_LIMIT> = indexer_selection::select(&candidates);
+    let mut query_deadline = Duration::from_secs(selection.query_deadline_secs);
+    if let Some(timeout) = query_settings.timeout {
+        query_deadline = query_deadline.min(timeout);
+    }
+    let selection_limit = selection.selection_limit.clamp(1, SELECTION_LIMIT);
+    // The candidates preferred by the client are selected first. The other candidates are only
+    // selected once the preferred candidates are exhausted.
+    let mut fallback_candidates = Vec::new();
+    let preferred = &query_settings.preferred_indexers;
+    if candidates.iter().any(|c| preferred.contains(&c.id)) {
+        (candidates, fallback_candidates) = candidates
+            .into_iter()
+            .partition(|c| preferred.contains(&c.id));
+    }
+    while !(candidates.is_empty() && fallback_candidates.is_empty())
+        && (start_time.elapsed() < query_deadline)
+    {
+        if candidates.is_empty() {
+            std::mem::swap(&mut candidates, &mut fallback_candidates);
+        }
+        let mut selections: ArrayVec<_, SELECTION_LIMIT> = indexer_selection::select(&candidates);
+        selections.truncate(selection_limit);
         if selections.is_empty() {
             // Candidates that would never be selected should be filtered out for improved errors.
             tracing::error!("no candidates selected");
             break;
         }
 
+        // Without hedging, all selected indexers are sent the request at once. With hedging, the
+        // request is sent to the next selected indexer only if the previous requests failed, or
+        // if no response was received within the deployment's hedging delay.
+        let hedge_delay = ctx.hedging.map(|hedging| {
+            ctx.indexing_perf
+                .latency_percentile(&selections[0].data.deployment, hedging.percentile)
+                .map(|latency_ms| Duration::from_millis(latency_ms as u64))
+                .unwrap_or(Duration::from_millis(hedging.default_delay_ms))
+                .max(Duration::from_millis(hedging.min_delay_ms))
+        });
         let (tx, mut rx) = mpsc::channel(SELECTION_LIMIT);
         let min_fee = *ctx.budgeter.min_indexer_fees.borrow();
-        for &selection in &selections {
+        // With hedging, the requests are usually sent to a single indexer.
+        let min_fee_split = if hedge_delay.is_some() {
+            1
+        } else {
+            selections.len()
+        };
+        let send_indexer_request = |selection: &Candidate<IndexerId, CandidateMetadata>,
+                                    tx: &mpsc::Sender<reports::IndexerRequest>,
+                                    indexer_errors: &mut IndexerErrors|
+         -> bool {
             let indexer = selection.id;
             let deployment = selection.data.deployment;
             let largest_allocation = selection.data.largest_allocation;
@@ -304,25 +491,45 @@ async fn run_indexer_queries(
             let subgraph_chain = subgraph.chain.clone();
 
             // over-pay indexers to hit target
-            let min_fee = *(min_fee.0 * grt_per_usd * one_grt) / selections.len() as f64;
+            let min_fee = *(min_fee.0 * grt_per_usd * one_grt) / min_fee_split as f64;
             let indexer_fee = selection.fee.as_f64() * budget as f64;
             let fee = indexer_fee.max(min_fee) as u128;
+            // The candidates list only excludes indexers with insufficient escrow for the
+            // cost-model fee. Check again for the fee signed in the receipt.
+            if let Some(escrow) = ctx.escrow.filter(|_| !legacy_scalar) {
+                if !escrow.is_available(&indexer, fee) {
+                    METRICS
+                        .escrow_insufficient
+                        .with_label_values(&[&indexer.to_string()])
+                        .inc();
+                    indexer_errors.insert(
+                        indexer,
+                        IndexerError::Unavailable(UnavailableReason::InsufficientEscrow),
+                    );
+                    return false;
+                }
+            }
             let receipt = match if legacy_scalar {
                 ctx.receipt_signer
                     .create_legacy_receipt(largest_allocation, fee)
             } else {
-                ctx.receipt_signer.create_receipt(largest_allocation, fee)
+                ctx.receipt_signer
+                    .create_receipt(indexer, largest_allocation, fee)
             } {
                 Ok(receipt) => receipt,
                 Err(err) => {
                     tracing::error!(?indexer, %deployment, error=?err, "failed to create receipt");
-                    continue;
+                    return false;
                 }
             };
             debug_assert!(fee == receipt.grt_value());
+            if let Some(circuit_breaker) = ctx.circuit_breaker {
+                circuit_breaker.on_request(&indexer, &deployment);
+            }
 
             let blocks_behind = blocks_behind(seconds_behind, blocks_per_minute);
             let indexer_client = ctx.indexer_client.clone();
+            let attestation_domain = ctx.attestation_domain;
             let indexer_query = indexer_query.clone();
             let tx = tx.clone();
             tokio::spawn(
@@ -330,7 +537,7 @@ async fn run_indexer_queries(
                     let start_time = Instant::now();
                     // URL checked: ref df8e647b-1e6e-422a-8846-dc9ee7e0dcc2
                     let deployment_url = url.join(&format!("subgraphs/id/{}", deployment)).unwrap();
-                    let auth = IndexerAuth::Paid(&receipt, ctx.attestation_domain);
+                    let auth = IndexerAuth::Paid(&receipt, attestation_domain);
                     let result = indexer_client
                         .query_indexer(deployment_url, auth, &indexer_query)
                         .in_current_span()
@@ -353,19 +560,101 @@ async fn run_indexer_queries(
                 }
                 .instrument(info_span!("indexer_request", ?indexer)),
             );
-        }
-        drop(tx);
+            true
+        };
 
-        while let Some(report) = rx.recv().await {
-            match report.result.as_ref() {
+        let mut pending = selections.iter();
+        let mut in_flight: usize = pending
+            .by_ref()
+            .take(if hedge_delay.is_some() {
+                1
+            } else {
+                SELECTION_LIMIT
+            })
+            .filter(|&&selection| send_indexer_request(selection, &tx, &mut indexer_errors))
+            .count();
+        let mut tx = Some(tx);
+
+        loop {
+            // Drop the sender once no more requests will be sent, so that the receiver is closed
+            // instead of waiting forever for reports from request tasks that panicked.
+            if pending.as_slice().is_empty() || client_response_time.is_some() {
+                tx = None;
+            }
+            let report = match hedge_delay {
+                _ if in_flight == 0 => None,
+                Some(hedge_delay) if tx.is_some() => {
+                    match tokio::time::timeout(hedge_delay, rx.recv()).await {
+                        Ok(report) => report,
+                        Err(_) => {
+                            tracing::debug!(
+                                hedge_delay_ms = hedge_delay.as_millis() as u64,
+                                "hedge"
+                            );
+                            None
+                        }
+                    }
+                }
+                _ => match rx.recv().await {
+                    Some(report) => Some(report),
+                    None => {
+                        tracing::error!(in_flight, "indexer requests dropped");
+                        break;
+                    }
+                },
+            };
+            let report = match report {
+                Some(report) => report,
+                // Either no requests are in flight, or the hedging delay has elapsed.
+                None => match (&tx, pending.next()) {
+                    (Some(tx), Some(&selection)) => {
+                        in_flight +=
+                            send_indexer_request(selection, tx, &mut indexer_errors) as usize;
+                        continue;
+                    }
+                    _ if in_flight == 0 => break,
+                    _ => continue,
+                },
+            };
+            in_flight -= 1;
+
+            // Responses without an attestation are valid, but not served to clients requiring one.
+            let client_result = match &report.result {
+                Ok(response)
+                    if query_settings.require_attestation && response.attestation.is_none() =>
+                {
+                    Err(IndexerError::BadResponse("missing attestation".into()))
+                }
+                Ok(response) => Ok(response),
+                Err(err) => Err(err.clone()),
+            };
+            match client_result {
                 Ok(response) if client_response_time.is_none() => {
-                    let _ = client_response.try_send(Ok(response.clone()));
+                    let served = ServedResponse {
+                        response: response.clone(),
+                        indexer: report.indexer,
+                        deployment: report.deployment,
+                        fee: report.receipt.grt_value(),
+                    };
+                    if let (Some(cache), Some(key)) = (ctx.response_cache, &cache_key) {
+                        if key.deployment == report.deployment {
+                            cache.insert(key.clone(), &served);
+                        }
+                    }
+                    let _ = client_response.try_send(Ok(served));
                     client_response_time = Some(start_time.elapsed());
                     client_response_bytes = Some(response.client_response.len() as u32);
                 }
                 Ok(_) => (),
                 Err(err) => {
-                    indexer_errors.insert(report.indexer, err.clone());
+                    indexer_errors.insert(report.indexer, err);
+                    // Don't wait for the hedging delay to retry after a failed request.
+                    if let (Some(tx), Some(_)) = (&tx, hedge_delay) {
+                        if let Some(&selection) = pending.next() {
+                            in_flight +=
+                                send_indexer_request(selection, tx, &mut indexer_errors) as usize;
+                        }
+                    }
                 }
             }
 
@@ -375,6 +664,7 @@ async fn run_indexer_queries(
                 Err(_) => ReceiptStatus::Failure,
             };
             ctx.receipt_signer.record_receipt(
+                report.indexer,
                 &report.largest_allocation,
                 &report.receipt,
                 receipt_status,
@@ -397,13 +687,20 @@ async fn run_indexer_queries(
         Some(client_response_time) => client_response_time,
         // Send fallback error to use when no indexers are successful.
         None => {
-            let _ = client_response.try_send(Err(Error::BadIndexers(indexer_errors.clone())));
+            let err = if stale {
+                Error::StaleIndexers(indexer_errors.clone())
+            } else {
+                Error::BadIndexers(indexer_errors.clone())
+            };
+            let _ = client_response.try_send(Err(err));
             start_time.elapsed()
         }
     };
 
-    let result = if indexer_requests.iter().any(|r| r.result.is_ok()) {
+    let result = if client_response_bytes.is_some() {
         Ok(())
+    } else if stale {
+        Err(Error::StaleIndexers(indexer_errors))
     } else {
         Err(Error::BadIndexers(indexer_errors))
     };
@@ -428,6 +725,13 @@ async fn run_indexer_queries(
             indexer_request.response_time_ms,
             latest_block,
         );
+        if let Some(circuit_breaker) = ctx.circuit_breaker {
+            circuit_breaker.feedback(
+                indexer_request.indexer,
+                indexer_request.deployment,
+                &indexer_request.result,
+            );
+        }
 
         if let Some(block) = indexer_request
             .result
@@ -435,7 +739,7 @@ async fn run_indexer_queries(
             .ok()
             .and_then(|r| r.probe_block.clone())
         {
-            chain.notify(block, indexer_request.indexer);
+            chain.notify(block, BlockSource::Indexer(indexer_request.indexer));
         }
 
         let deployment = indexer_request.deployment.to_string();
@@ -504,12 +808,14 @@ struct CandidateMetadata {
 #[allow(clippy::too_many_arguments)]
 fn build_candidates_list(
     ctx: &Context,
+    selection: &SelectionParams,
+    query_settings: &QuerySettings,
     context: &AgoraContext,
     budget: u128,
     chain_head: BlockNumber,
     blocks_per_minute: u64,
     block_requirements: &BlockRequirements,
-    subgraph_versions: &[DeploymentId],
+    deployment: DeploymentId,
     indexings: HashMap<IndexingId, Result<Indexing, network::ResolutionError>>,
 ) -> (
     Vec<Candidate<IndexerId, CandidateMetadata>>,
@@ -518,18 +824,6 @@ fn build_candidates_list(
     let mut candidates_list = Vec::new();
     let mut candidates_errors = BTreeMap::default();
 
-    // Select the latest subgraph version where indexers are near chain head, or else the latest.
-    let cutoff = chain_head.saturating_sub(blocks_per_minute * 30);
-    let deployment = *subgraph_versions
-        .iter()
-        .find(|v| {
-            indexings
-                .iter()
-                .filter_map(|(_, result)| result.as_ref().ok())
-                .any(|i| (i.id.deployment == **v) && (i.progress.latest_block > cutoff))
-        })
-        .unwrap_or(&subgraph_versions[0]);
-
     // Lock the indexing performance and get access to the latest performance snapshots
     let perf_snapshots = ctx.indexing_perf.latest();
 
@@ -547,12 +841,53 @@ fn build_candidates_list(
             continue;
         }
 
+        // If the indexer is excluded by the client, register an error and continue to the next
+        // indexer
+        if query_settings
+            .excluded_indexers
+            .contains(&indexing_id.indexer)
+        {
+            candidates_errors.insert(
+                indexing_id.indexer,
+                IndexerError::Unavailable(UnavailableReason::ExcludedByClient),
+            );
+            continue;
+        }
+
+        // If the indexer can't be paid, register an error and continue to the next indexer
+        if !indexing.indexer.tap_support && !ctx.receipt_signer.legacy_enabled() {
+            candidates_errors.insert(
+                indexing_id.indexer,
+                IndexerError::Unavailable(no_tap_support()),
+            );
+            continue;
+        }
+
+        // If the indexer's circuit breaker is open, register an error and continue to the next
+        // indexer
+        if let Some(circuit_breaker) = ctx.circuit_breaker {
+            if !circuit_breaker.is_available(&indexing_id.indexer, &indexing_id.deployment) {
+                candidates_errors.insert(
+                    indexing_id.indexer,
+                    IndexerError::Unavailable(UnavailableReason::CircuitBreakerOpen),
+                );
+                continue;
+            }
+        }
+
         // Get the performance snapshot for the indexer and calculate the expected performance.
         // If the indexer is not available, register an error and continue to the next indexer
         let perf = match perf_snapshots
             .get(&(indexing_id.indexer, indexing_id.deployment))
-            .and_then(|snapshot| perf(snapshot, block_requirements, chain_head, blocks_per_minute))
-        {
+            .and_then(|snapshot| {
+                perf(
+                    snapshot,
+                    selection,
+                    block_requirements,
+                    chain_head,
+                    blocks_per_minute,
+                )
+            }) {
             Some(perf) => perf,
             None => {
                 candidates_errors.insert(
@@ -601,9 +936,9 @@ fn build_candidates_list(
             }
         }
 
-        // Calculate the fee for the indexing, and normalize it
+        // Calculate the fee for the indexing
         let fee = match indexer_fee(context, &indexing.cost_model) {
-            Some(fee) => Normalized::new(fee as f64 / budget as f64).unwrap_or(Normalized::ONE),
+            Some(fee) => fee,
             None => {
                 candidates_errors.insert(
                     indexing_id.indexer,
@@ -613,6 +948,25 @@ fn build_candidates_list(
             }
         };
 
+        // If the indexer's escrow balance doesn't cover its outstanding TAP receipts and the fee,
+        // register an error and continue to the next indexer. The fee signed in the receipt may be
+        // larger, so escrow is checked again before sending the request.
+        if let Some(escrow) = ctx.escrow.filter(|_| indexing.indexer.tap_support) {
+            if !escrow.is_available(&indexing_id.indexer, fee) {
+                METRICS
+                    .escrow_insufficient
+                    .with_label_values(&[&indexing_id.indexer.to_string()])
+                    .inc();
+                candidates_errors.insert(
+                    indexing_id.indexer,
+                    IndexerError::Unavailable(UnavailableReason::InsufficientEscrow),
+                );
+                continue;
+            }
+        }
+
+        let fee = Normalized::new(fee as f64 / budget as f64).unwrap_or(Normalized::ONE);
+
         candidates_list.push(Candidate {
             id: indexing_id.indexer,
             data: CandidateMetadata {
@@ -629,7 +983,21 @@ fn build_candidates_list(
         });
     }
 
-    let seconds_behind_cutoff = 60 * 30;
+    // Unlike the cutoff below, the client's freshness requirement is strict. Candidates that don't
+    // meet it are never selected, even if no other candidates remain.
+    if block_requirements.latest {
+        candidates_list.retain(|c| {
+            let blocks_behind = blocks_behind(c.seconds_behind, blocks_per_minute);
+            if !query_settings.is_fresh(c.seconds_behind, blocks_behind) {
+                candidates_errors
+                    .insert(c.id, IndexerError::Unavailable(UnavailableReason::NotFresh));
+                return false;
+            }
+            true
+        });
+    }
+
+    let seconds_behind_cutoff = selection.seconds_behind_cutoff;
     if block_requirements.latest
         && candidates_list
             .iter()
@@ -650,6 +1018,34 @@ fn build_candidates_list(
     (candidates_list, candidates_errors)
 }
 
+/// Select the latest subgraph version where indexers are near chain head, or else the latest.
+/// Returns the selected deployment, with its effective selection parameters. The version cutoff is
+/// taken from the parameters of the latest version, since the deployment isn't known yet.
+fn select_deployment(
+    ctx: &Context,
+    subgraph: &ResolvedSubgraphInfo,
+    chain_head: BlockNumber,
+    blocks_per_minute: u64,
+) -> (DeploymentId, SelectionParams) {
+    let latest = ctx.selection.params(&subgraph.chain, &subgraph.versions[0]);
+    let cutoff = chain_head.saturating_sub(blocks_per_minute * latest.version_cutoff_minutes);
+    let deployment = *subgraph
+        .versions
+        .iter()
+        .find(|v| {
+            subgraph
+                .indexings
+                .values()
+                .filter_map(|result| result.as_ref().ok())
+                .any(|i| (i.id.deployment == **v) && (i.progress.latest_block > cutoff))
+        })
+        .unwrap_or(&subgraph.versions[0]);
+    (
+        deployment,
+        ctx.selection.params(&subgraph.chain, &deployment),
+    )
+}
+
 struct Perf {
     response: indexer_selection::ExpectedPerformance,
     latest_block: BlockNumber,
@@ -658,6 +1054,7 @@ struct Perf {
 
 fn perf(
     snapshot: &indexing_performance::Snapshot,
+    selection: &SelectionParams,
     block_requirements: &BlockRequirements,
     chain_head: BlockNumber,
     blocks_per_minute: u64,
@@ -674,7 +1071,7 @@ fn perf(
     // Since our gateway is specialized for frontends, add an additional penalty for candidates
     // far behind chain head. This compensates for the impacts of information decay and the sharp
     // dropoff of our `seconds_behind` curve.
-    if seconds_behind > (60 * 30) {
+    if seconds_behind > selection.seconds_behind_cutoff {
         response.success_rate = Normalized::ZERO;
     }
 
@@ -689,6 +1086,11 @@ fn blocks_behind(seconds_behind: u32, blocks_per_minute: u64) -> u64 {
     ((seconds_behind as f64 / 60.0) * blocks_per_minute as f64) as u64
 }
 
+/// Unavailable reason of indexers without TAP support, when legacy receipts are disabled.
+fn no_tap_support() -> UnavailableReason {
+    UnavailableReason::NotSupported("no TAP support, and legacy receipts are disabled".to_string())
+}
+
 /// Estimate the fee for an indexer based on the cost model and the query context.
 ///
 /// If the cost model is not available, the fee is assumed to be zero.
@@ -769,14 +1171,19 @@ pub async fn handle_indexer_query(
         .unwrap_or(0);
     let seconds_behind = ((blocks_behind as f64 * 60.0) / blocks_per_minute as f64) as u32;
 
+    if !indexing.indexer.tap_support && !ctx.receipt_signer.legacy_enabled() {
+        return Err(bad_indexers(IndexerError::Unavailable(no_tap_support())));
+    }
+
     // Use budget as fee.
     let grt_per_usd = *ctx.grt_per_usd.borrow();
     let one_grt = NotNan::new(1e18).unwrap();
-    let fee = *(ctx.budgeter.query_fees_target.0 * grt_per_usd * one_grt) as u128;
+    let query_fees_target = ctx.budgeter.query_fees_target.borrow().0;
+    let fee = *(query_fees_target * grt_per_usd * one_grt) as u128;
 
     let allocation = indexing.largest_allocation;
     let receipt = match if indexing.indexer.tap_support {
-        ctx.receipt_signer.create_receipt(allocation, fee)
+        ctx.receipt_signer.create_receipt(indexer, allocation, fee)
     } else {
         ctx.receipt_signer.create_legacy_receipt(allocation, fee)
     } {
@@ -815,6 +1222,18 @@ pub async fn handle_indexer_query(
         request: payload,
     };
 
+    let receipt_status = match &indexer_request.result {
+        Ok(_) => ReceiptStatus::Success,
+        Err(IndexerError::Timeout) => ReceiptStatus::Unknown,
+        Err(_) => ReceiptStatus::Failure,
+    };
+    ctx.receipt_signer.record_receipt(
+        indexer_request.indexer,
+        &indexer_request.largest_allocation,
+        &indexer_request.receipt,
+        receipt_status,
+    );
+
     let report_result = match &result {
         Ok(_) => Ok(()),
         Err(err) => Err(bad_indexers(err.clone())),
@@ -859,6 +1278,7 @@ pub async fn handle_indexer_query(
         latest_block,
     );
 
+    let fee = indexer_request.receipt.grt_value();
     let _ = ctx.reporter.send(reports::ClientRequest {
         id: request_id,
         response_time_ms,
@@ -871,20 +1291,17 @@ pub async fn handle_indexer_query(
         indexer_requests: vec![indexer_request],
     });
 
-    result.map(
-        |IndexerResponse {
-             client_response,
-             attestation,
-             ..
-         }| {
-            Response::builder()
-                .status(StatusCode::OK)
-                .header_typed(ContentType::json())
-                .header_typed(GraphAttestation(attestation))
-                .body(client_response)
-                .unwrap()
-        },
-    )
+    result.map(|response| {
+        client_response(
+            &ctx,
+            ServedResponse {
+                response,
+                indexer: indexing_id.indexer,
+                deployment: indexing_id.deployment,
+                fee,
+            },
+        )
+    })
 }
 
 #[cfg(test)]
@@ -916,6 +1333,7 @@ mod tests {
                 payment_required: false,
                 api_keys: watch::channel(Default::default()).1,
                 special_api_keys: Default::default(),
+                rate_limiter: Default::default(),
             };
             if let Some(key) = key {
                 ctx.api_keys = watch::channel(HashMap::from([(
@@ -1082,4 +1500,40 @@ mod tests {
             });
         }
     }
+
+    mod client_response {
+        use thegraph_core::{deployment_id, Address, IndexerId};
+
+        use super::super::{client_response, ServedResponse, GRAPH_CACHED, GRAPH_FEE_GRT_WEI};
+        use crate::indexer_client::IndexerResponse;
+
+        fn served() -> ServedResponse {
+            ServedResponse {
+                response: IndexerResponse {
+                    original_response: r#"{"data":{"a":1}}"#.to_string(),
+                    attestation: None,
+                    client_response: r#"{"data":{"a":1}}"#.to_string(),
+                    errors: vec![],
+                    probe_block: None,
+                },
+                indexer: IndexerId::from(Address::ZERO),
+                deployment: deployment_id!("QmeYTH2fK2wv96XvnCGH2eyKFE8kmRfo53zYVy5dKysZtH"),
+                fee: 1000,
+            }
+        }
+
+        #[test]
+        fn cached_response_reports_no_fee() {
+            //* When
+            let served_res = client_response(true, served(), false);
+            let cached_res = client_response(true, served(), true);
+
+            //* Then
+            assert_eq!(served_res.headers()[&GRAPH_FEE_GRT_WEI], "1000");
+            assert_eq!(served_res.headers().get(&GRAPH_CACHED), None);
+            assert_eq!(cached_res.headers()[&GRAPH_FEE_GRT_WEI], "0");
+            assert_eq!(cached_res.headers()[&GRAPH_CACHED], "true");
+            assert_eq!(cached_res.body(), served_res.body());
+        }
+    }
 }
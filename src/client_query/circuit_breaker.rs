@@ -0,0 +1,232 @@
+//! Circuit breaker for indexers failing to respond.
+//!
+//! The indexing performance decays the success rate of failing indexers, but they are still
+//! selected at a low rate, which costs a receipt each time. A circuit breaker is kept per indexer,
+//! and per indexing. It trips (opens) after a number of consecutive connection failures, timeouts,
+//! or 5xx responses. While open, the indexer (or indexing) is excluded from selection. After the
+//! cooldown, the circuit is half-open: a single request is let through as a probe, closing the
+//! circuit on success, or opening it again on failure.
+
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    time::{Duration, Instant},
+};
+
+use parking_lot::Mutex;
+use thegraph_core::{DeploymentId, IndexerId};
+
+use crate::errors::IndexerError;
+
+pub struct CircuitBreaker {
+    failure_threshold: u32,
+    cooldown: Duration,
+    indexers: Mutex<HashMap<IndexerId, Breaker>>,
+    indexings: Mutex<HashMap<(IndexerId, DeploymentId), Breaker>>,
+}
+
+#[derive(Default)]
+struct Breaker {
+    consecutive_failures: u32,
+    open_until: Option<Instant>,
+    probe_sent_at: Option<Instant>,
+}
+
+impl Breaker {
+    fn is_available(&self, cooldown: Duration, now: Instant) -> bool {
+        match self.open_until {
+            None => true,
+            Some(open_until) if now < open_until => false,
+            // half-open, unless a probe is already in flight
+            Some(_) => self
+                .probe_sent_at
+                .map(|t| now.duration_since(t) >= cooldown)
+                .unwrap_or(true),
+        }
+    }
+
+    fn on_request(&mut self, now: Instant) {
+        if self.open_until.is_some_and(|t| now >= t) {
+            self.probe_sent_at = Some(now);
+        }
+    }
+
+    /// Record a failure. Returns true if the circuit was opened.
+    fn on_failure(&mut self, threshold: u32, cooldown: Duration, now: Instant) -> bool {
+        self.consecutive_failures += 1;
+        let half_open = self.open_until.is_some_and(|t| now >= t);
+        if half_open || (self.consecutive_failures >= threshold) {
+            self.open_until = Some(now + cooldown);
+            self.probe_sent_at = None;
+            return true;
+        }
+        false
+    }
+}
+
+impl CircuitBreaker {
+    pub fn new(failure_threshold: u32, cooldown: Duration) -> Self {
+        Self {
+            failure_threshold: failure_threshold.max(1),
+            cooldown,
+            indexers: Default::default(),
+            indexings: Default::default(),
+        }
+    }
+
+    /// Returns false if the circuit of the indexer, or of the indexing, is open.
+    pub fn is_available(&self, indexer: &IndexerId, deployment: &DeploymentId) -> bool {
+        let now = Instant::now();
+        let available = |breaker: Option<&Breaker>| {
+            breaker
+                .map(|b| b.is_available(self.cooldown, now))
+                .unwrap_or(true)
+        };
+        available(self.indexers.lock().get(indexer))
+            && available(self.indexings.lock().get(&(*indexer, *deployment)))
+    }
+
+    /// Record a request sent to the indexer. If the circuit is half-open, this request is the
+    /// probe.
+    pub fn on_request(&self, indexer: &IndexerId, deployment: &DeploymentId) {
+        let now = Instant::now();
+        if let Some(breaker) = self.indexers.lock().get_mut(indexer) {
+            breaker.on_request(now);
+        }
+        if let Some(breaker) = self.indexings.lock().get_mut(&(*indexer, *deployment)) {
+            breaker.on_request(now);
+        }
+    }
+
+    /// Record the result of a request sent to the indexer.
+    pub fn feedback<T>(
+        &self,
+        indexer: IndexerId,
+        deployment: DeploymentId,
+        result: &Result<T, IndexerError>,
+    ) {
+        let failure = matches!(result, Err(err) if is_failure(err));
+        let now = Instant::now();
+        let update = |breakers: &mut HashMap<_, Breaker>, key| {
+            update_breaker(
+                breakers,
+                key,
+                failure,
+                self.failure_threshold,
+                self.cooldown,
+                now,
+            )
+        };
+        if update(&mut self.indexers.lock(), indexer) {
+            tracing::warn!(?indexer, "indexer circuit breaker open");
+        }
+        if update(&mut self.indexings.lock(), (indexer, deployment)) {
+            tracing::warn!(?indexer, %deployment, "indexing circuit breaker open");
+        }
+    }
+}
+
+fn update_breaker<K: Eq + Hash>(
+    breakers: &mut HashMap<K, Breaker>,
+    key: K,
+    failure: bool,
+    threshold: u32,
+    cooldown: Duration,
+    now: Instant,
+) -> bool {
+    if !failure {
+        breakers.remove(&key);
+        return false;
+    }
+    breakers
+        .entry(key)
+        .or_default()
+        .on_failure(threshold, cooldown, now)
+}
+
+/// Errors indicating that the indexer is unable to respond at all.
+fn is_failure(err: &IndexerError) -> bool {
+    match err {
+        IndexerError::Timeout => true,
+        IndexerError::BadResponse(message) => {
+            (message == "failed to connect")
+                || message
+                    .parse::<u16>()
+                    .is_ok_and(|status| (500..600).contains(&status))
+        }
+        IndexerError::Internal(_) | IndexerError::Unavailable(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use thegraph_core::{deployment_id, Address};
+
+    use super::*;
+
+    #[test]
+    fn circuit_breaker() {
+        //* Given
+        let breaker = CircuitBreaker::new(3, Duration::ZERO);
+        let indexer = IndexerId::from(Address::ZERO);
+        let deployment = deployment_id!("QmeYTH2fK2wv96XvnCGH2eyKFE8kmRfo53zYVy5dKysZtH");
+        let failure: Result<(), IndexerError> =
+            Err(IndexerError::BadResponse("failed to connect".to_string()));
+        let ok: Result<(), IndexerError> = Ok(());
+
+        //* When
+        breaker.feedback(indexer, deployment, &failure);
+        breaker.feedback(indexer, deployment, &failure);
+        breaker.feedback(indexer, deployment, &ok);
+        breaker.feedback(indexer, deployment, &failure);
+        breaker.feedback(indexer, deployment, &failure);
+        let available_before_trip = breaker.is_available(&indexer, &deployment);
+        breaker.feedback(indexer, deployment, &Err(IndexerError::Timeout));
+
+        //* Then
+        assert!(available_before_trip);
+        // With a zero cooldown, the circuit is half-open immediately.
+        assert!(breaker.is_available(&indexer, &deployment));
+        breaker.on_request(&indexer, &deployment);
+        // The probe failing opens the circuit again.
+        breaker.feedback(indexer, deployment, &Err(IndexerError::Timeout));
+        assert!(breaker
+            .indexers
+            .lock()
+            .get(&indexer)
+            .unwrap()
+            .open_until
+            .is_some());
+        // The probe succeeding closes the circuit.
+        breaker.on_request(&indexer, &deployment);
+        breaker.feedback(indexer, deployment, &ok);
+        assert!(breaker.indexers.lock().is_empty());
+        assert!(breaker.indexings.lock().is_empty());
+    }
+
+    #[test]
+    fn open_circuit_excludes_indexer() {
+        //* Given
+        let breaker = CircuitBreaker::new(1, Duration::from_secs(60));
+        let indexer = IndexerId::from(Address::ZERO);
+        let deployment = deployment_id!("QmeYTH2fK2wv96XvnCGH2eyKFE8kmRfo53zYVy5dKysZtH");
+        let other_deployment = deployment_id!("QmWmyoMoctfbAaiEs2G46gpeUmhqFRDW6KWo64y5r581Vz");
+
+        //* When
+        breaker.feedback(
+            indexer,
+            deployment,
+            &Err::<(), _>(IndexerError::BadResponse("503".to_string())),
+        );
+        breaker.feedback(
+            IndexerId::from(Address::repeat_byte(1)),
+            deployment,
+            &Err::<(), _>(IndexerError::BadResponse("400".to_string())),
+        );
+
+        //* Then
+        assert!(!breaker.is_available(&indexer, &deployment));
+        assert!(!breaker.is_available(&indexer, &other_deployment));
+        assert!(breaker.is_available(&IndexerId::from(Address::repeat_byte(1)), &deployment));
+    }
+}
@@ -2,9 +2,15 @@ use alloy_sol_types::Eip712Domain;
 use ordered_float::NotNan;
 use tokio::sync::{mpsc, watch};
 
+use super::{circuit_breaker::CircuitBreaker, response_cache::ResponseCache};
 use crate::{
-    budgets::Budgeter, chains::Chains, indexer_client::IndexerClient,
-    indexing_performance::IndexingPerformance, network::NetworkService, receipts::ReceiptSigner,
+    budgets::Budgeter,
+    chains::Chains,
+    config::{HedgingConfig, SelectionConfig},
+    indexer_client::IndexerClient,
+    indexing_performance::IndexingPerformance,
+    network::NetworkService,
+    receipts::{escrow::Escrow, ReceiptSigner},
     reports,
 };
 
@@ -19,4 +25,10 @@ pub struct Context {
     pub indexing_perf: IndexingPerformance,
     pub attestation_domain: &'static Eip712Domain,
     pub reporter: mpsc::UnboundedSender<reports::ClientRequest>,
+    pub response_cache: Option<&'static ResponseCache>,
+    pub hedging: Option<HedgingConfig>,
+    pub circuit_breaker: Option<&'static CircuitBreaker>,
+    pub escrow: Option<&'static Escrow>,
+    pub selection: &'static SelectionConfig,
+    pub response_metadata_headers: bool,
 }
@@ -0,0 +1,131 @@
+//! Candidate selection dry-run.
+//!
+//! Runs the candidate selection preparation steps for a client query, without sending any indexer
+//! requests or creating any receipts, and reports how each indexer was considered.
+
+use std::collections::BTreeMap;
+
+use anyhow::anyhow;
+use axum::{body::Bytes, extract::State, Extension};
+use cost_model::Context as AgoraContext;
+use indexer_selection::ArrayVec;
+use prost::bytes::Buf as _;
+use serde_json::json;
+
+use super::{
+    build_candidates_list, context::Context, query_budget, query_selector::QuerySelector,
+    query_settings::QuerySettings, resolve_chain_state, resolve_subgraph_info, select_deployment,
+    QueryBody, SELECTION_LIMIT,
+};
+use crate::{
+    auth::AuthSettings,
+    errors::Error,
+    json::{json_response, JsonResponse},
+};
+
+pub async fn handle_dry_run(
+    State(ctx): State<Context>,
+    Extension(auth): Extension<AuthSettings>,
+    query_settings: Option<Extension<QuerySettings>>,
+    selector: QuerySelector,
+    payload: Bytes,
+) -> Result<JsonResponse, Error> {
+    // Check if the query selector is authorized by the auth token and
+    // resolve the subgraph deployments for the query.
+    let subgraph = resolve_subgraph_info(&ctx, &auth, selector).await?;
+
+    let client_request: QueryBody =
+        serde_json::from_reader(payload.reader()).map_err(|err| Error::BadQuery(err.into()))?;
+    let variables = client_request
+        .variables
+        .as_ref()
+        .map(ToString::to_string)
+        .unwrap_or_default();
+    let agora_context = AgoraContext::new(&client_request.query, &variables)
+        .map_err(|err| Error::BadQuery(anyhow!("{err}")))?;
+
+    let query_settings = query_settings
+        .map(|Extension(settings)| settings)
+        .unwrap_or_default();
+
+    let (chain_head, blocks_per_minute, block_requirements) = resolve_chain_state(
+        &ctx.chains.chain(&subgraph.chain).read(),
+        &subgraph,
+        &agora_context,
+    )?;
+    let (deployment, selection) = select_deployment(&ctx, &subgraph, chain_head, blocks_per_minute);
+    let budget = query_budget(&ctx, &selection, &query_settings);
+
+    let (candidates, indexer_errors) = build_candidates_list(
+        &ctx,
+        &selection,
+        &query_settings,
+        &agora_context,
+        budget,
+        chain_head,
+        blocks_per_minute,
+        &block_requirements,
+        deployment,
+        subgraph.indexings,
+    );
+
+    // The selection algorithm has no side effects. The candidates it would select on the first
+    // attempt are reported alongside the full candidates list. The candidates preferred by the
+    // client are selected first.
+    let (preferred_candidates, other_candidates): (Vec<_>, Vec<_>) = candidates
+        .into_iter()
+        .partition(|c| query_settings.preferred_indexers.contains(&c.id));
+    let mut selections: ArrayVec<_, SELECTION_LIMIT> =
+        indexer_selection::select(if preferred_candidates.is_empty() {
+            &other_candidates
+        } else {
+            &preferred_candidates
+        });
+    selections.truncate(selection.selection_limit.clamp(1, SELECTION_LIMIT));
+
+    let candidates = preferred_candidates
+        .iter()
+        .chain(&other_candidates)
+        .map(|candidate| {
+            json!({
+                "indexer": candidate.id,
+                "deployment": candidate.data.deployment,
+                "url": candidate.data.url.to_string(),
+                "largest_allocation": candidate.data.largest_allocation,
+                "tap_support": candidate.data.tap_support,
+                "fee": candidate.fee.as_f64(),
+                "fee_grt": candidate.fee.as_f64() * budget as f64 * 1e-18,
+                "success_rate": candidate.perf.success_rate.as_f64(),
+                "latency_ms": candidate.perf.latency_ms(),
+                "seconds_behind": candidate.seconds_behind,
+                "slashable_grt": candidate.slashable_grt,
+                "zero_allocation": candidate.zero_allocation,
+            })
+        })
+        .collect::<Vec<_>>();
+    let selections = selections.iter().map(|s| s.id).collect::<Vec<_>>();
+    // Keyed by the indexer ID in its serialized form, like the other indexer fields.
+    let indexer_errors = indexer_errors
+        .iter()
+        .map(|(indexer, err)| (*indexer, err.to_string()))
+        .collect::<BTreeMap<_, _>>();
+
+    Ok(json_response(
+        [],
+        json!({
+            "chain": subgraph.chain,
+            "chain_head": chain_head,
+            "blocks_per_minute": blocks_per_minute,
+            "block_requirements": {
+                "range": block_requirements.range,
+                "number_gte": block_requirements.number_gte,
+                "latest": block_requirements.latest,
+            },
+            "budget_grt": budget as f64 * 1e-18,
+            "selection": selection,
+            "candidates": candidates,
+            "selections": selections,
+            "indexer_errors": indexer_errors,
+        }),
+    ))
+}
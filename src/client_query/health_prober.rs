@@ -0,0 +1,178 @@
+//! Health probes to indexings without recent client traffic.
+//!
+//! The indexing performance is only updated by the client queries selecting an indexing. So
+//! indexings that are rarely selected, like those of new indexers, keep cold performance entries.
+//! The prober periodically sends a small `_meta` query to the indexings without recent feedback,
+//! paying the fee from their cost model, and feeds the result back into the indexing performance
+//! and the chain head tracking. Indexings are not probed if their fee exceeds the configured
+//! maximum, or if their escrow balance doesn't cover it.
+
+use std::time::{Duration, Instant};
+
+use cost_model::Context as AgoraContext;
+use futures::future::join_all;
+use ordered_float::NotNan;
+use tokio::time::{interval, MissedTickBehavior};
+use tracing::Instrument as _;
+
+use super::{context::Context, indexer_fee};
+use crate::{
+    chain::BlockSource,
+    errors::IndexerError,
+    indexer_client::IndexerAuth,
+    metrics::METRICS,
+    network::{Indexing, IndexingId},
+    receipts::ReceiptStatus,
+};
+
+const PROBE_QUERY: &str = "{ _gateway_probe_: _meta { block { number hash timestamp } } }";
+
+/// Spawn a task sending, every `probe_interval`, up to `max_probes` health probes to the indexings
+/// without feedback within `max_idle`. Each probe pays at most `max_fee_usd`.
+pub fn spawn(
+    ctx: Context,
+    probe_interval: Duration,
+    max_idle: Duration,
+    max_probes: usize,
+    max_fee_usd: NotNan<f64>,
+) {
+    let indexer_query = serde_json::json!({ "query": PROBE_QUERY }).to_string();
+    let agora_context = AgoraContext::new(PROBE_QUERY, "").expect("invalid probe query");
+    tokio::spawn(async move {
+        let mut interval = interval(probe_interval);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            let grt_per_usd = *ctx.grt_per_usd.borrow();
+            let max_fee = *(max_fee_usd * grt_per_usd * NotNan::new(1e18).unwrap()) as u128;
+            let targets = probe_targets(&ctx, max_idle, max_probes);
+            tracing::debug!(health_probes = targets.len());
+            let probes = targets.into_iter().filter_map(|(indexing, chain)| {
+                let fee = indexer_fee(&agora_context, &indexing.cost_model)?;
+                if fee > max_fee {
+                    return None;
+                }
+                let escrow = ctx.escrow.filter(|_| indexing.indexer.tap_support);
+                if escrow.is_some_and(|escrow| !escrow.is_available(&indexing.id.indexer, fee)) {
+                    return None;
+                }
+                Some(probe(&ctx, indexing, chain, fee, &indexer_query))
+            });
+            join_all(probes).await;
+        }
+    });
+}
+
+/// Select the available indexings, without an open circuit breaker, and without feedback within
+/// `max_idle`. Starting with the indexings that had no feedback for the longest time.
+fn probe_targets(ctx: &Context, max_idle: Duration, max_probes: usize) -> Vec<(Indexing, String)> {
+    let now = Instant::now();
+    let perf = ctx.indexing_perf.latest();
+    let mut targets: Vec<(Option<Instant>, Indexing, String)> = ctx
+        .network
+        .snapshot()
+        .deployments
+        .values()
+        .filter_map(|deployment| deployment.as_ref().ok())
+        .flat_map(|deployment| {
+            deployment
+                .indexings
+                .values()
+                .filter_map(|indexing| indexing.as_ref().ok())
+                .map(|indexing| (indexing, deployment.chain.clone()))
+        })
+        .filter(|(indexing, _)| indexing.indexer.tap_support || ctx.receipt_signer.legacy_enabled())
+        .filter(|(indexing, _)| {
+            ctx.circuit_breaker
+                .map(|breaker| breaker.is_available(&indexing.id.indexer, &indexing.id.deployment))
+                .unwrap_or(true)
+        })
+        .filter_map(|(indexing, chain)| {
+            let last_feedback = perf
+                .get(&(indexing.id.indexer, indexing.id.deployment))
+                .and_then(|snapshot| snapshot.last_feedback);
+            if last_feedback.is_some_and(|t| now.duration_since(t) < max_idle) {
+                return None;
+            }
+            Some((last_feedback, indexing.clone(), chain))
+        })
+        .collect();
+    targets.sort_unstable_by_key(|(last_feedback, _, _)| *last_feedback);
+    targets
+        .into_iter()
+        .take(max_probes)
+        .map(|(_, indexing, chain)| (indexing, chain))
+        .collect()
+}
+
+async fn probe(ctx: &Context, indexing: Indexing, chain: String, fee: u128, indexer_query: &str) {
+    let IndexingId {
+        indexer,
+        deployment,
+    } = indexing.id;
+    let allocation = indexing.largest_allocation;
+    let receipt = match if indexing.indexer.tap_support {
+        ctx.receipt_signer.create_receipt(indexer, allocation, fee)
+    } else {
+        ctx.receipt_signer.create_legacy_receipt(allocation, fee)
+    } {
+        Ok(receipt) => receipt,
+        Err(err) => {
+            tracing::error!(?indexer, %deployment, error = ?err, "failed to create receipt");
+            return;
+        }
+    };
+    METRICS
+        .health_probe_fees
+        .inc_by(receipt.grt_value() as f64 * 1e-18);
+    if let Some(circuit_breaker) = ctx.circuit_breaker {
+        circuit_breaker.on_request(&indexer, &deployment);
+    }
+
+    // URL checked: ref df8e647b-1e6e-422a-8846-dc9ee7e0dcc2
+    let deployment_url = indexing
+        .indexer
+        .url
+        .join(&format!("subgraphs/id/{}", deployment))
+        .unwrap();
+    let auth = IndexerAuth::Paid(&receipt, ctx.attestation_domain);
+    let start_time = Instant::now();
+    let result = ctx
+        .indexer_client
+        .query_indexer(deployment_url, auth, indexer_query)
+        .instrument(tracing::info_span!("health_probe", ?indexer, %deployment))
+        .await;
+    let response_time_ms = start_time.elapsed().as_millis() as u16;
+
+    let receipt_status = match &result {
+        Ok(_) => ReceiptStatus::Success,
+        Err(IndexerError::Timeout) => ReceiptStatus::Unknown,
+        Err(_) => ReceiptStatus::Failure,
+    };
+    ctx.receipt_signer
+        .record_receipt(indexer, &allocation, &receipt, receipt_status);
+
+    let probe_block = result.as_ref().ok().and_then(|r| r.probe_block.clone());
+    ctx.indexing_perf.feedback(
+        indexer,
+        deployment,
+        result.is_ok(),
+        response_time_ms,
+        probe_block.as_ref().map(|b| b.number),
+    );
+    if let Some(circuit_breaker) = ctx.circuit_breaker {
+        circuit_breaker.feedback(indexer, deployment, &result);
+    }
+    if let Some(block) = probe_block {
+        ctx.chains
+            .chain(&chain)
+            .notify(block, BlockSource::Indexer(indexer));
+    }
+    tracing::debug!(
+        ?indexer,
+        %deployment,
+        result = ?result.as_ref().map(|_| ()),
+        response_time_ms,
+        "health_probe"
+    );
+}
@@ -1,7 +1,201 @@
+use std::time::Duration;
+
+use anyhow::{anyhow, ensure, Context as _};
+use axum::http::{HeaderMap, HeaderName};
 use ordered_float::NotNan;
+use thegraph_core::{Address, IndexerId};
+
+use crate::{auth::AuthSettings, errors::Error};
+
+/// Maximum budget for the query, in USD.
+pub static MAX_BUDGET_USD: HeaderName = HeaderName::from_static("graph-max-budget-usd");
+/// Maximum time, in seconds, that selected indexers may be behind chain head.
+pub static MAX_SECONDS_BEHIND: HeaderName = HeaderName::from_static("graph-max-seconds-behind");
+/// Maximum number of blocks that selected indexers may be behind chain head.
+pub static MAX_BLOCKS_BEHIND: HeaderName = HeaderName::from_static("graph-max-blocks-behind");
+/// Comma-separated indexer addresses to select before any other indexers.
+pub static PREFERRED_INDEXERS: HeaderName = HeaderName::from_static("graph-preferred-indexers");
+/// Comma-separated indexer addresses to never select.
+pub static EXCLUDED_INDEXERS: HeaderName = HeaderName::from_static("graph-excluded-indexers");
+/// Reject indexer responses without an attestation (`true` or `false`).
+pub static REQUIRE_ATTESTATION: HeaderName = HeaderName::from_static("graph-require-attestation");
+/// Time after which no more indexers are selected, in milliseconds.
+pub static TIMEOUT_MS: HeaderName = HeaderName::from_static("graph-timeout-ms");
+
+/// Lower bound on the client timeout. Shorter timeouts would count otherwise healthy indexers as
+/// failing to respond.
+const MIN_TIMEOUT: Duration = Duration::from_secs(1);
 
 /// User query settings typically associated with an auth token.
 #[derive(Clone, Debug, Default)]
 pub struct QuerySettings {
     pub budget_usd: Option<NotNan<f64>>,
+    pub max_seconds_behind: Option<u32>,
+    pub max_blocks_behind: Option<u64>,
+    pub preferred_indexers: Vec<IndexerId>,
+    pub excluded_indexers: Vec<IndexerId>,
+    pub require_attestation: bool,
+    pub timeout: Option<Duration>,
+}
+
+impl QuerySettings {
+    /// Parse the query settings from the client request headers, and check them against the
+    /// limits of the API key. Without a budget header, the API key's budget is used.
+    pub fn from_headers(headers: &HeaderMap, auth: &AuthSettings) -> Result<Self, Error> {
+        let budget_usd = parse_header(headers, &MAX_BUDGET_USD, |value| {
+            let budget = NotNan::new(value.parse::<f64>()?)?;
+            ensure!(*budget > 0.0, "budget must be positive");
+            Ok(budget)
+        })?;
+        let budget_usd = match (budget_usd, auth.budget_usd) {
+            (Some(budget), Some(limit)) if budget > limit => {
+                return Err(Error::Auth(anyhow!("budget exceeds the API key limit")));
+            }
+            (budget, limit) => budget.or(limit),
+        };
+        let timeout = parse_header(headers, &TIMEOUT_MS, |value| {
+            let timeout = Duration::from_millis(value.parse()?);
+            ensure!(
+                timeout >= MIN_TIMEOUT,
+                "timeout below {}ms",
+                MIN_TIMEOUT.as_millis()
+            );
+            Ok(timeout)
+        })?;
+        Ok(Self {
+            budget_usd,
+            max_seconds_behind: parse_header(headers, &MAX_SECONDS_BEHIND, |value| {
+                Ok(value.parse()?)
+            })?,
+            max_blocks_behind: parse_header(headers, &MAX_BLOCKS_BEHIND, |value| {
+                Ok(value.parse()?)
+            })?,
+            preferred_indexers: parse_header(headers, &PREFERRED_INDEXERS, parse_indexers)?
+                .unwrap_or_default(),
+            excluded_indexers: parse_header(headers, &EXCLUDED_INDEXERS, parse_indexers)?
+                .unwrap_or_default(),
+            require_attestation: parse_header(headers, &REQUIRE_ATTESTATION, |value| {
+                Ok(value.parse()?)
+            })?
+            .unwrap_or(false),
+            timeout,
+        })
+    }
+
+    /// Returns false if the indexer is further behind chain head than required by the client.
+    pub fn is_fresh(&self, seconds_behind: u32, blocks_behind: u64) -> bool {
+        self.max_seconds_behind
+            .map(|max| seconds_behind <= max)
+            .unwrap_or(true)
+            && self
+                .max_blocks_behind
+                .map(|max| blocks_behind <= max)
+                .unwrap_or(true)
+    }
+}
+
+fn parse_header<T>(
+    headers: &HeaderMap,
+    name: &HeaderName,
+    parse: impl FnOnce(&str) -> anyhow::Result<T>,
+) -> Result<Option<T>, Error> {
+    let value = match headers.get(name) {
+        Some(value) => value,
+        None => return Ok(None),
+    };
+    value
+        .to_str()
+        .map_err(anyhow::Error::from)
+        .and_then(|value| parse(value.trim()))
+        .map(Some)
+        .with_context(|| format!("invalid {name} header"))
+        .map_err(Error::BadQuery)
+}
+
+fn parse_indexers(value: &str) -> anyhow::Result<Vec<IndexerId>> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(|s| Ok(IndexerId::from(s.parse::<Address>()?)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::HeaderValue;
+
+    use super::*;
+
+    fn headers(entries: &[(&HeaderName, &str)]) -> HeaderMap {
+        entries
+            .iter()
+            .map(|(name, value)| ((*name).clone(), HeaderValue::from_str(value).unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn parse_query_settings() {
+        //* Given
+        let auth = AuthSettings {
+            budget_usd: Some(NotNan::new(1e-3).unwrap()),
+            ..Default::default()
+        };
+        let headers = headers(&[
+            (&MAX_BUDGET_USD, "0.0005"),
+            (&MAX_SECONDS_BEHIND, "120"),
+            (&MAX_BLOCKS_BEHIND, "10"),
+            (
+                &EXCLUDED_INDEXERS,
+                "0x0000000000000000000000000000000000000001, 0x0000000000000000000000000000000000000002",
+            ),
+            (&REQUIRE_ATTESTATION, "true"),
+            (&TIMEOUT_MS, "5000"),
+        ]);
+
+        //* When
+        let settings = QuerySettings::from_headers(&headers, &auth).unwrap();
+
+        //* Then
+        assert_eq!(settings.budget_usd, Some(NotNan::new(5e-4).unwrap()));
+        assert_eq!(settings.max_seconds_behind, Some(120));
+        assert_eq!(settings.max_blocks_behind, Some(10));
+        assert!(settings.is_fresh(120, 10));
+        assert!(!settings.is_fresh(121, 10));
+        assert!(!settings.is_fresh(120, 11));
+        assert!(settings.preferred_indexers.is_empty());
+        assert_eq!(
+            settings.excluded_indexers,
+            vec![
+                IndexerId::from(Address::with_last_byte(1)),
+                IndexerId::from(Address::with_last_byte(2)),
+            ]
+        );
+        assert!(settings.require_attestation);
+        assert_eq!(settings.timeout, Some(Duration::from_secs(5)));
+    }
+
+    #[test]
+    fn query_settings_are_checked_against_api_key_limits() {
+        let auth = AuthSettings {
+            budget_usd: Some(NotNan::new(1e-3).unwrap()),
+            ..Default::default()
+        };
+
+        // Without a budget header, the API key budget is used.
+        let settings = QuerySettings::from_headers(&HeaderMap::new(), &auth).unwrap();
+        assert_eq!(settings.budget_usd, auth.budget_usd);
+
+        let err =
+            QuerySettings::from_headers(&headers(&[(&MAX_BUDGET_USD, "1")]), &auth).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "auth error: budget exceeds the API key limit"
+        );
+        let err = QuerySettings::from_headers(&headers(&[(&TIMEOUT_MS, "10")]), &auth).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "bad query: invalid graph-timeout-ms header: timeout below 1000ms"
+        );
+    }
 }
@@ -0,0 +1,128 @@
+//! Response cache for block-pinned queries.
+//!
+//! Queries where all block constraints resolve to specific block hashes are deterministic. So the
+//! attested indexer responses to these queries can be served again, without paying indexers. The
+//! indexing that served the response, and the fee paid for it, are cached with the response.
+
+use std::{collections::BTreeSet, time::Duration};
+
+use parking_lot::Mutex;
+use thegraph_core::{BlockHash, DeploymentId};
+
+use super::ServedResponse;
+use crate::ttl_hash_map::TtlHashMap;
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct CacheKey {
+    pub deployment: DeploymentId,
+    /// The indexer query, as rewritten by the gateway
+    pub query: String,
+    /// The blocks the query is pinned to
+    pub blocks: BTreeSet<BlockHash>,
+}
+
+pub struct ResponseCache {
+    /// Minimum number of blocks between the chain head and a block number constraint for the
+    /// query to be cacheable.
+    pub min_confirmations: u64,
+    max_entries: usize,
+    entries: Mutex<TtlHashMap<CacheKey, ServedResponse>>,
+}
+
+impl ResponseCache {
+    pub fn new(ttl: Duration, max_entries: usize, min_confirmations: u64) -> Self {
+        Self {
+            min_confirmations,
+            max_entries,
+            entries: Mutex::new(TtlHashMap::with_ttl(ttl)),
+        }
+    }
+
+    pub(super) fn get(&self, key: &CacheKey) -> Option<ServedResponse> {
+        self.entries.lock().get(key).cloned()
+    }
+
+    /// Insert the response into the cache. Only attested responses without errors are cached. If
+    /// the cache is full, after removing the expired entries, the response is not cached.
+    pub(super) fn insert(&self, key: CacheKey, served: &ServedResponse) {
+        let response = &served.response;
+        if response.attestation.is_none() || !response.errors.is_empty() {
+            return;
+        }
+        let mut entries = self.entries.lock();
+        if entries.len_all() >= self.max_entries {
+            entries.cleanup();
+            if entries.len_all() >= self.max_entries {
+                return;
+            }
+        }
+        entries.insert(key, served.clone());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use thegraph_core::{deployment_id, Address, Attestation, IndexerId};
+
+    use super::*;
+    use crate::indexer_client::IndexerResponse;
+
+    fn response(attested: bool) -> ServedResponse {
+        let response = IndexerResponse {
+            original_response: r#"{"data":{"a":1}}"#.to_string(),
+            attestation: attested.then(|| Attestation {
+                request_cid: Default::default(),
+                response_cid: Default::default(),
+                deployment: Default::default(),
+                r: Default::default(),
+                s: Default::default(),
+                v: 0,
+            }),
+            client_response: r#"{"data":{"a":1}}"#.to_string(),
+            errors: vec![],
+            probe_block: None,
+        };
+        ServedResponse {
+            response,
+            indexer: IndexerId::from(Address::ZERO),
+            deployment: key("").deployment,
+            fee: 1000,
+        }
+    }
+
+    fn key(query: &str) -> CacheKey {
+        CacheKey {
+            deployment: deployment_id!("QmeYTH2fK2wv96XvnCGH2eyKFE8kmRfo53zYVy5dKysZtH"),
+            query: query.to_string(),
+            blocks: [BlockHash::ZERO].into(),
+        }
+    }
+
+    #[test]
+    fn only_attested_responses_are_cached() {
+        //* Given
+        let cache = ResponseCache::new(Duration::from_secs(60), 10, 0);
+
+        //* When
+        cache.insert(key("{ a }"), &response(true));
+        cache.insert(key("{ b }"), &response(false));
+
+        //* Then
+        assert!(cache.get(&key("{ a }")).is_some());
+        assert!(cache.get(&key("{ b }")).is_none());
+    }
+
+    #[test]
+    fn cache_is_bounded() {
+        //* Given
+        let cache = ResponseCache::new(Duration::from_secs(60), 1, 0);
+
+        //* When
+        cache.insert(key("{ a }"), &response(true));
+        cache.insert(key("{ b }"), &response(true));
+
+        //* Then
+        assert!(cache.get(&key("{ a }")).is_some());
+        assert!(cache.get(&key("{ b }")).is_none());
+    }
+}
@@ -2,6 +2,7 @@
 
 use std::{
     collections::{BTreeMap, HashSet},
+    num::NonZeroU64,
     ops::Deref,
     path::{Path, PathBuf},
     str::FromStr,
@@ -14,7 +15,7 @@ use ipnetwork::IpNetwork;
 use ordered_float::NotNan;
 use secp256k1::SecretKey;
 use semver::Version;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use serde_with::{serde_as, DeserializeAs, DisplayFromStr};
 use thegraph_core::{Address, DeploymentId};
 use url::Url;
@@ -26,7 +27,7 @@ use crate::{
 
 /// The Graph Gateway configuration.
 #[serde_as]
-#[derive(CustomDebug, Deserialize)]
+#[derive(Clone, CustomDebug, Deserialize)]
 pub struct Config {
     #[serde(default)]
     pub api_keys: Option<ApiKeys>,
@@ -37,12 +38,27 @@ pub struct Config {
     /// Chain aliases
     #[serde(default)]
     pub chain_aliases: BTreeMap<String, String>,
+    /// Exclude indexers from selection after repeated failures to respond (optional)
+    pub circuit_breaker: Option<CircuitBreakerConfig>,
+    /// JSON-RPC providers used to poll the chain head, by chain name
+    #[serde(default)]
+    pub chain_rpcs: BTreeMap<String, ChainRpcConfig>,
     /// Ethereum RPC provider, or fixed exchange rate for testing
     pub exchange_rate_provider: ExchangeRateProvider,
+    /// Send indexer requests to one indexer at a time, and only send the request to the next
+    /// selected indexer after a delay (optional)
+    pub hedging: Option<HedgingConfig>,
+    /// Send health probes to indexings without recent client queries (optional)
+    pub health_probes: Option<HealthProbesConfig>,
     /// Graph network environment identifier, inserted into Kafka messages
     pub graph_env_id: String,
     /// File path of CSV containing rows of `IpNetwork,Country`
     pub ip_blocker_db: Option<PathBuf>,
+    /// File path of the indexing performance cache, used to warm-start indexer selection
+    /// (optional)
+    pub indexing_performance_cache: Option<PathBuf>,
+    /// Share the indexing performance with other gateway replicas over Kafka (optional)
+    pub indexing_performance_exchange: Option<IndexingPerformanceExchangeConfig>,
     /// IP rate limit in requests per second
     pub ip_rate_limit: u16,
     /// See https://github.com/confluentinc/librdkafka/blob/master/CONFIGURATION.md
@@ -56,6 +72,8 @@ pub struct Config {
     /// Minimum indexer-service version that will receive queries
     #[serde_as(as = "DisplayFromStr")]
     pub min_indexer_version: Version,
+    /// File path of the network cache, used to warm-start the network topology (optional)
+    pub network_cache: Option<PathBuf>,
     /// Indexers used to query the network subgraph
     pub trusted_indexers: Vec<TrustedIndexer>,
     /// Check payment state of client (disable for testnets)
@@ -65,6 +83,9 @@ pub struct Config {
     pub poi_blocklist: Vec<ProofOfIndexingInfo>,
     /// POI blocklist update interval in minutes (default: 20 minutes)
     pub poi_blocklist_update_interval: Option<u64>,
+    /// private admin API port (default: served on the metrics port, under `/admin`)
+    #[serde(default)]
+    pub port_admin: Option<u16>,
     /// public API port
     pub port_api: u16,
     /// private metrics port
@@ -73,6 +94,98 @@ pub struct Config {
     #[serde(deserialize_with = "deserialize_not_nan_f64")]
     pub query_fees_target: NotNan<f64>,
     pub receipts: Receipts,
+    /// Response cache for queries pinned to specific blocks (optional)
+    pub response_cache: Option<ResponseCacheConfig>,
+    /// Return the served block, indexer, deployment, and fee in response headers (default: false)
+    #[serde(default)]
+    pub response_metadata_headers: bool,
+    /// Destination of the gateway's reports (default: Kafka, using the `kafka` settings)
+    #[serde(default)]
+    pub reports: ReportSinkConfig,
+    /// Candidate selection parameters, with per-chain and per-deployment overrides
+    #[serde(default)]
+    pub selection: SelectionConfig,
+}
+
+impl Config {
+    /// Returns the names of the fields that differ from `other`, but that cannot be applied
+    /// without restarting the gateway.
+    pub fn restart_required(&self, other: &Self) -> Vec<&'static str> {
+        let mut fields = Vec::new();
+        let mut check = |name: &'static str, changed: bool| {
+            if changed {
+                fields.push(name);
+            }
+        };
+
+        // A fixed set of API keys can be replaced at runtime, the API keys endpoint can't.
+        let api_keys_updatable = matches!(
+            (&self.api_keys, &other.api_keys),
+            (Some(ApiKeys::Fixed(_)), Some(ApiKeys::Fixed(_)))
+        );
+        check(
+            "api_keys",
+            !api_keys_updatable && (self.api_keys != other.api_keys),
+        );
+        check("attestations", self.attestations != other.attestations);
+        check("chain_rpcs", self.chain_rpcs != other.chain_rpcs);
+        check(
+            "circuit_breaker",
+            self.circuit_breaker != other.circuit_breaker,
+        );
+        check(
+            "exchange_rate_provider",
+            self.exchange_rate_provider != other.exchange_rate_provider,
+        );
+        check("graph_env_id", self.graph_env_id != other.graph_env_id);
+        check("health_probes", self.health_probes != other.health_probes);
+        check("hedging", self.hedging != other.hedging);
+        check(
+            "indexing_performance_cache",
+            self.indexing_performance_cache != other.indexing_performance_cache,
+        );
+        check(
+            "indexing_performance_exchange",
+            self.indexing_performance_exchange != other.indexing_performance_exchange,
+        );
+        check("ip_blocker_db", self.ip_blocker_db != other.ip_blocker_db);
+        check("ip_rate_limit", self.ip_rate_limit != other.ip_rate_limit);
+        check("kafka", self.kafka != other.kafka);
+        check("log_json", self.log_json != other.log_json);
+        check("network_cache", self.network_cache != other.network_cache);
+        check(
+            "trusted_indexers",
+            self.trusted_indexers != other.trusted_indexers,
+        );
+        check(
+            "payment_required",
+            self.payment_required != other.payment_required,
+        );
+        check(
+            "poi_blocklist_update_interval",
+            self.poi_blocklist_update_interval != other.poi_blocklist_update_interval,
+        );
+        check("port_admin", self.port_admin != other.port_admin);
+        check("port_api", self.port_api != other.port_api);
+        check("port_metrics", self.port_metrics != other.port_metrics);
+        // The TAP signer rotation can be updated at runtime.
+        check(
+            "receipts",
+            self.receipts.without_signer_rotation() != other.receipts.without_signer_rotation(),
+        );
+        check("reports", self.reports != other.reports);
+        check(
+            "response_cache",
+            self.response_cache != other.response_cache,
+        );
+        check(
+            "response_metadata_headers",
+            self.response_metadata_headers != other.response_metadata_headers,
+        );
+        check("selection", self.selection != other.selection);
+
+        fields
+    }
 }
 
 /// Deserialize a `NotNan<f64>` from a `f64` and return an error if the value is NaN.
@@ -84,11 +197,25 @@ where
     NotNan::new(value).map_err(serde::de::Error::custom)
 }
 
+fn deserialize_nonzero_u64<'de, D>(deserializer: D) -> Result<u64, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    NonZeroU64::deserialize(deserializer).map(NonZeroU64::get)
+}
+
+fn deserialize_nonzero_u64_opt<'de, D>(deserializer: D) -> Result<Option<u64>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    Option::<NonZeroU64>::deserialize(deserializer).map(|value| value.map(NonZeroU64::get))
+}
+
 /// API keys configuration.
 ///
 /// See [`Config`]'s [`api_keys`](struct.Config.html#structfield.api_keys).
 #[serde_as]
-#[derive(Debug, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Deserialize)]
 #[serde(untagged)]
 pub enum ApiKeys {
     Endpoint {
@@ -105,7 +232,7 @@ pub enum ApiKeys {
     Fixed(Vec<APIKey>),
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Deserialize)]
 pub struct BlockedIndexer {
     /// empty array blocks on all deployments
     pub deployments: Vec<DeploymentId>,
@@ -115,17 +242,43 @@ pub struct BlockedIndexer {
 /// Attestation configuration.
 ///
 /// See [`Config`]'s [`attestations`](struct.Config.html#structfield.attestations).
-#[derive(Debug, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Deserialize)]
 pub struct AttestationConfig {
     pub chain_id: String,
     pub dispute_manager: Address,
 }
 
+/// Chain head polling configuration.
+///
+/// See [`Config`]'s [`chain_rpcs`](struct.Config.html#structfield.chain_rpcs).
+#[serde_as]
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct ChainRpcConfig {
+    /// JSON-RPC provider URL
+    #[serde_as(as = "DisplayFromStr")]
+    pub url: Url,
+    /// Interval between chain head requests, in seconds (default: 5). Must be greater than 0.
+    #[serde(default, deserialize_with = "deserialize_nonzero_u64_opt")]
+    pub poll_interval_secs: Option<u64>,
+}
+
+/// Indexer circuit breaker configuration.
+///
+/// See [`Config`]'s [`circuit_breaker`](struct.Config.html#structfield.circuit_breaker).
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct CircuitBreakerConfig {
+    /// Number of consecutive connection failures, timeouts, or 5xx responses before the indexer
+    /// is excluded from selection
+    pub failure_threshold: u32,
+    /// Time before a single probe request is sent to an excluded indexer, in seconds
+    pub cooldown_secs: u64,
+}
+
 /// The exchange rate provider.
 ///
 /// See [`Config`]'s [`exchange_rate_provider`](struct.Config.html#structfield.exchange_rate_provider).
 #[serde_as]
-#[derive(Debug, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Deserialize)]
 #[serde(untagged)]
 pub enum ExchangeRateProvider {
     /// Ethereum RPC provider
@@ -134,10 +287,57 @@ pub enum ExchangeRateProvider {
     Fixed(#[serde(deserialize_with = "deserialize_not_nan_f64")] NotNan<f64>),
 }
 
+/// Indexing health probes configuration.
+///
+/// See [`Config`]'s [`health_probes`](struct.Config.html#structfield.health_probes).
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct HealthProbesConfig {
+    /// Interval between rounds of health probes, in seconds. Must be greater than 0.
+    #[serde(deserialize_with = "deserialize_nonzero_u64")]
+    pub interval_secs: u64,
+    /// Time without indexer responses after which an indexing is probed, in seconds
+    pub max_idle_secs: u64,
+    /// Maximum number of health probes sent per round
+    pub max_probes: usize,
+    /// Maximum fee paid for a health probe, in USD. Indexings with a higher fee are not probed.
+    #[serde(deserialize_with = "deserialize_not_nan_f64")]
+    pub max_fee_usd: NotNan<f64>,
+}
+
+/// Hedged indexer requests configuration.
+///
+/// See [`Config`]'s [`hedging`](struct.Config.html#structfield.hedging).
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+pub struct HedgingConfig {
+    /// Percentile, in the range [0, 1], of the deployment's recent response latencies used as the
+    /// hedging delay
+    pub percentile: f64,
+    /// Hedging delay used when there are not enough recent responses for the deployment, in
+    /// milliseconds
+    pub default_delay_ms: u64,
+    /// Minimum hedging delay, in milliseconds
+    pub min_delay_ms: u64,
+}
+
+/// Indexing performance exchange configuration.
+///
+/// See [`Config`]'s [`indexing_performance_exchange`](struct.Config.html#structfield.indexing_performance_exchange).
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct IndexingPerformanceExchangeConfig {
+    /// Kafka topic shared by the gateway replicas
+    pub topic: String,
+    /// Interval between publishing the local feedback, in seconds. Must be greater than 0.
+    #[serde(deserialize_with = "deserialize_nonzero_u64")]
+    pub publish_interval_secs: u64,
+    /// Weight, in the range [0, 1], of the feedback from other replicas relative to the local
+    /// feedback
+    pub remote_weight: f64,
+}
+
 /// Kafka configuration.
 ///
 /// See [`Config`]'s [`kafka`](struct.Config.html#structfield.kafka).
-#[derive(Debug, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Deserialize)]
 pub struct KafkaConfig(BTreeMap<String, String>);
 
 impl Default for KafkaConfig {
@@ -170,19 +370,245 @@ impl From<KafkaConfig> for rdkafka::config::ClientConfig {
     }
 }
 
+/// Response cache configuration.
+///
+/// See [`Config`]'s [`response_cache`](struct.Config.html#structfield.response_cache).
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct ResponseCacheConfig {
+    /// Maximum number of cached responses
+    pub max_entries: usize,
+    /// Minimum number of blocks between the chain head and a `number` block constraint for the
+    /// query to be cached. Queries constrained by block `hash` are always cached.
+    pub min_confirmations: u64,
+    /// Time to live of cached responses, in seconds
+    pub ttl_secs: u64,
+}
+
+/// Destination of the gateway's reports.
+///
+/// See [`Config`]'s [`reports`](struct.Config.html#structfield.reports).
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ReportSinkConfig {
+    /// Send reports to Kafka topics, using the [`Config`]'s `kafka` settings
+    #[default]
+    Kafka,
+    /// Write reports as newline-delimited JSON to a file
+    File {
+        path: PathBuf,
+        /// Size at which the file is rotated (default: 100 MiB)
+        max_file_bytes: Option<u64>,
+    },
+    /// Write reports as newline-delimited JSON to stdout
+    Stdout,
+}
+
+/// Candidate selection parameters.
+///
+/// See [`Config`]'s [`selection`](struct.Config.html#structfield.selection).
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct SelectionConfig {
+    /// Overrides of the default parameters, for all queries
+    #[serde(flatten)]
+    pub defaults: SelectionOverrides,
+    /// Overrides by chain name
+    #[serde(default)]
+    pub chains: BTreeMap<String, SelectionOverrides>,
+    /// Overrides by deployment, taking precedence over the chain overrides. These apply to
+    /// queries by deployment ID, and to queries by subgraph ID where the deployment is the
+    /// selected subgraph version. The version is selected using the `version_cutoff_minutes` of
+    /// the latest version.
+    #[serde(default)]
+    pub deployments: BTreeMap<DeploymentId, SelectionOverrides>,
+}
+
+impl SelectionConfig {
+    /// Returns the selection parameters for queries without chain or deployment overrides.
+    pub fn default_params(&self) -> SelectionParams {
+        let mut params = SelectionParams::default();
+        self.defaults.apply(&mut params);
+        params
+    }
+
+    /// Returns the effective selection parameters for queries on the given chain and deployment.
+    pub fn params(&self, chain: &str, deployment: &DeploymentId) -> SelectionParams {
+        let mut params = self.default_params();
+        if let Some(overrides) = self.chains.get(chain) {
+            overrides.apply(&mut params);
+        }
+        if let Some(overrides) = self.deployments.get(deployment) {
+            overrides.apply(&mut params);
+        }
+        params
+    }
+}
+
+/// Candidate selection parameters, where unset fields keep their previous value.
+///
+/// See [`SelectionParams`] for the description of each field.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct SelectionOverrides {
+    pub selection_limit: Option<usize>,
+    pub query_deadline_secs: Option<u64>,
+    pub seconds_behind_cutoff: Option<u32>,
+    pub version_cutoff_minutes: Option<u64>,
+    pub user_budget_max_factor: Option<u32>,
+}
+
+impl SelectionOverrides {
+    fn apply(&self, params: &mut SelectionParams) {
+        let Self {
+            selection_limit,
+            query_deadline_secs,
+            seconds_behind_cutoff,
+            version_cutoff_minutes,
+            user_budget_max_factor,
+        } = self;
+        if let Some(value) = selection_limit {
+            params.selection_limit = *value;
+        }
+        if let Some(value) = query_deadline_secs {
+            params.query_deadline_secs = *value;
+        }
+        if let Some(value) = seconds_behind_cutoff {
+            params.seconds_behind_cutoff = *value;
+        }
+        if let Some(value) = version_cutoff_minutes {
+            params.version_cutoff_minutes = *value;
+        }
+        if let Some(value) = user_budget_max_factor {
+            params.user_budget_max_factor = *value;
+        }
+    }
+}
+
+/// The effective candidate selection parameters of a query.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub struct SelectionParams {
+    /// Maximum number of indexers selected at once for a query, in the range [1, 3]
+    /// (default: 3)
+    pub selection_limit: usize,
+    /// Time after which no more indexers are selected for a query, in seconds (default: 60)
+    pub query_deadline_secs: u64,
+    /// Indexers further behind chain head, in seconds, are excluded from queries for the latest
+    /// blocks, if other indexers are within the cutoff (default: 1800)
+    pub seconds_behind_cutoff: u32,
+    /// Queries by subgraph ID go to the latest subgraph version with an indexer within this many
+    /// minutes of chain head (default: 30)
+    pub version_cutoff_minutes: u64,
+    /// Maximum factor between the budget set by the client and the default budget (default: 10)
+    pub user_budget_max_factor: u32,
+}
+
+impl Default for SelectionParams {
+    fn default() -> Self {
+        Self {
+            selection_limit: 3,
+            query_deadline_secs: 60,
+            seconds_behind_cutoff: 60 * 30,
+            version_cutoff_minutes: 30,
+            user_budget_max_factor: 10,
+        }
+    }
+}
+
 #[serde_as]
-#[derive(Debug, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Deserialize)]
 pub struct Receipts {
     /// TAP verifier contract chain
     pub chain_id: U256,
-    /// Secret key for legacy voucher signing (Scalar)
+    /// Secret key for legacy voucher signing (Scalar), defaulting to `signer`. Unlike the TAP
+    /// signer, this key isn't affected by `signer_rotation` and can't be rotated:
+    /// `/collect-receipts`, `/partial-voucher`, and `/voucher` only accept receipts signed by the
+    /// current key, so receipts issued with a replaced key can no longer be redeemed.
     #[serde_as(as = "Option<HiddenSecretKey>")]
     pub legacy_signer: Option<Hidden<SecretKey>>,
-    /// TAP signer key
+    /// Stop issuing legacy (Scalar) receipts, and exclude indexers without TAP support from
+    /// selection. Legacy vouchers are still served for the receipts issued before.
+    #[serde(default)]
+    pub legacy_disabled: bool,
+    /// TAP signer key. The gateway ID is the address of this key, also when rotating to other
+    /// keys.
     #[serde_as(as = "HiddenSecretKey")]
     pub signer: Hidden<SecretKey>,
+    /// TAP signer keys to rotate to, on schedule. These can be updated on config reload.
+    #[serde(default)]
+    pub signer_rotation: Vec<RotatedSigner>,
+    /// Time, in seconds, that receipts from a superseded TAP signer remain accepted for RAV
+    /// requests (default: 604800). Previous RAVs are accepted from all configured signers.
+    pub signer_grace_period_secs: Option<u64>,
     /// TAP verifier contract address
     pub verifier: Address,
+    /// Ledger of the issued receipts (optional)
+    #[serde(default)]
+    pub ledger: Option<ReceiptLedgerConfig>,
+    /// Stop selecting indexers once their outstanding TAP receipt value would exceed their escrow
+    /// balance (optional)
+    #[serde(default)]
+    pub escrow: Option<EscrowConfig>,
+}
+
+/// TAP signer key, and the time at which it becomes the active signer.
+///
+/// See [`Receipts`]'s [`signer_rotation`](struct.Receipts.html#structfield.signer_rotation).
+#[serde_as]
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct RotatedSigner {
+    #[serde_as(as = "HiddenSecretKey")]
+    pub key: Hidden<SecretKey>,
+    /// Activation time, in seconds since the Unix epoch
+    pub active_from: u64,
+}
+
+impl Receipts {
+    /// Returns these settings without the ones that can be updated at runtime.
+    fn without_signer_rotation(&self) -> Self {
+        Self {
+            signer_rotation: Vec::new(),
+            signer_grace_period_secs: None,
+            ..self.clone()
+        }
+    }
+}
+
+/// Append-only ledger of the issued receipts.
+///
+/// See [`Receipts`]'s [`ledger`](struct.Receipts.html#structfield.ledger).
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct ReceiptLedgerConfig {
+    /// Directory of the ledger segment files
+    pub path: PathBuf,
+    /// Size at which a new segment file is started (default: 100 MiB)
+    pub max_segment_bytes: Option<u64>,
+}
+
+/// Source of the gateway's escrow balances for each indexer.
+///
+/// See [`Receipts`]'s [`escrow`](struct.Receipts.html#structfield.escrow).
+#[serde_as]
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum EscrowConfig {
+    /// Fixed escrow balances by indexer address, in GRT wei
+    Fixed {
+        #[serde_as(as = "BTreeMap<_, DisplayFromStr>")]
+        balances: BTreeMap<Address, u128>,
+    },
+    /// Poll the escrow balances from the escrow subgraph
+    Subgraph {
+        /// Escrow subgraph endpoints, queried in order
+        indexers: Vec<TrustedIndexer>,
+        /// TAP sender address, owning the escrow accounts
+        sender: Address,
+        /// Interval between escrow balance updates, in seconds (default: 60). Must be greater
+        /// than 0.
+        #[serde(default, deserialize_with = "deserialize_nonzero_u64_opt")]
+        poll_interval_secs: Option<u64>,
+        /// Don't limit indexers until the escrow balances are known, e.g. when the first poll
+        /// fails. By default, TAP indexers are unavailable until then.
+        #[serde(default)]
+        allow_unknown_balances: bool,
+    },
 }
 
 /// Load the configuration from a JSON file.
@@ -215,7 +641,7 @@ pub enum Error {
     Deserialize(#[from] serde_json::Error),
 }
 
-#[derive(Clone, Deserialize)]
+#[derive(Clone, PartialEq, Deserialize)]
 #[serde(transparent)]
 pub struct Hidden<T>(pub T);
 
@@ -32,6 +32,9 @@ pub enum Error {
     /// Indexers are available, but failed to return a suitable result.
     #[error("bad indexers: {0}")]
     BadIndexers(IndexerErrors),
+    /// No indexer is within the freshness requirement of the client.
+    #[error("no indexers within freshness requirement: {0}")]
+    StaleIndexers(IndexerErrors),
 }
 
 impl IntoResponse for Error {
@@ -114,6 +117,22 @@ pub enum UnavailableReason {
     #[error("too far behind")]
     TooFarBehind,
 
+    /// The indexer is further behind chain head than required by the client.
+    #[error("not fresh")]
+    NotFresh,
+
+    /// The indexer is excluded by the client's query settings.
+    #[error("excluded by client")]
+    ExcludedByClient,
+
+    /// The indexer's circuit breaker is open, after repeatedly failing to respond.
+    #[error("circuit breaker open")]
+    CircuitBreakerOpen,
+
+    /// The indexer's outstanding TAP receipt value would exceed its escrow balance.
+    #[error("insufficient escrow")]
+    InsufficientEscrow,
+
     /// An internal error occurred.
     #[error("internal error: {0}")]
     Internal(&'static str),
@@ -1,4 +1,4 @@
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use thegraph_core::DeploymentId;
 use thegraph_graphql_http::{
     graphql::{Document, IntoDocument, IntoDocumentWithVariables},
@@ -106,7 +106,7 @@ struct Response {
     cost_models: Vec<CostModelSource>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CostModelSource {
     pub deployment: DeploymentId,
     pub model: String,
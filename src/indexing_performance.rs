@@ -1,21 +1,37 @@
-use std::{collections::HashMap, ops::Deref, time::Duration};
+use std::{
+    collections::{HashMap, VecDeque},
+    fs,
+    ops::Deref,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
 
-use parking_lot::RwLock;
+use anyhow::Context as _;
+use parking_lot::{Mutex, RwLock};
+use serde::{Deserialize, Serialize};
 use thegraph_core::{BlockNumber, DeploymentId, IndexerId};
 use tokio::{self, sync::mpsc, time::MissedTickBehavior};
 
-use crate::network::NetworkService;
+use self::exchange::Outbox;
+use crate::{metrics::METRICS, network::NetworkService, time::unix_timestamp};
 
-#[derive(Default)]
+mod exchange;
+
+#[derive(Clone, Default)]
 pub struct Snapshot {
     pub response: indexer_selection::Performance,
     pub latest_block: Option<BlockNumber>,
+    /// When the last indexer response feedback was received
+    pub last_feedback: Option<Instant>,
 }
 
 #[derive(Clone)]
 pub struct IndexingPerformance {
     data: &'static DoubleBuffer,
-    msgs: mpsc::UnboundedSender<Feedback>,
+    latencies: &'static RwLock<HashMap<DeploymentId, LatencySamples>>,
+    msgs: mpsc::UnboundedSender<Msg>,
+    /// Local feedback to share with other gateway replicas, if enabled
+    outbox: &'static Mutex<Option<Outbox>>,
 }
 
 struct Feedback {
@@ -26,12 +42,53 @@ struct Feedback {
     latest_block: Option<BlockNumber>,
 }
 
+enum Msg {
+    Feedback(Feedback),
+    /// Feedback observed by another gateway replica. It only updates the expected response
+    /// performance of the indexing.
+    RemoteFeedback {
+        indexer: IndexerId,
+        deployment: DeploymentId,
+        success: bool,
+        latency_ms: u16,
+    },
+}
+
 impl IndexingPerformance {
-    pub fn new(network: NetworkService) -> Self {
+    /// Create the indexing performance, restored from the cache file if available. The
+    /// performance is stored to the cache file periodically.
+    pub fn new(network: NetworkService, cache_path: Option<PathBuf>) -> Self {
         let (tx, rx) = mpsc::unbounded_channel();
         let data: &'static DoubleBuffer = Box::leak(Box::default());
-        Actor::spawn(data, rx, network);
-        Self { data, msgs: tx }
+        let latencies: &'static RwLock<HashMap<DeploymentId, LatencySamples>> =
+            Box::leak(Box::default());
+        match cache_path.as_deref().filter(|path| path.exists()).map(load) {
+            None => (),
+            Some(Err(indexing_perf_load_err)) => {
+                tracing::warn!(indexing_perf_load_err = format!("{indexing_perf_load_err:#}"));
+            }
+            Some(Ok(cache)) => {
+                let age_s = unix_timestamp().saturating_sub(cache.timestamp) / 1_000;
+                let restored = cache.restore(age_s);
+                *data.0[0].write() = restored.clone();
+                *data.0[1].write() = restored;
+                METRICS
+                    .indexing_performance_restored
+                    .set(cache.indexings.len() as i64);
+                tracing::info!(
+                    age_s,
+                    indexings = cache.indexings.len(),
+                    "indexing performance restored from cache"
+                );
+            }
+        };
+        Actor::spawn(data, latencies, rx, network, cache_path);
+        Self {
+            data,
+            latencies,
+            msgs: tx,
+            outbox: Box::leak(Box::default()),
+        }
     }
 
     pub fn latest(&self) -> impl Deref<Target = HashMap<(IndexerId, DeploymentId), Snapshot>> + '_ {
@@ -47,6 +104,16 @@ impl IndexingPerformance {
         }
     }
 
+    /// Return the given percentile (in the range [0, 1]) of the recent successful response
+    /// latencies of the deployment, across all of its indexers. Returns `None` if there are not
+    /// enough samples.
+    pub fn latency_percentile(&self, deployment: &DeploymentId, percentile: f64) -> Option<u16> {
+        self.latencies
+            .read()
+            .get(deployment)?
+            .percentile(percentile)
+    }
+
     pub fn feedback(
         &self,
         indexer: IndexerId,
@@ -55,34 +122,170 @@ impl IndexingPerformance {
         latency_ms: u16,
         latest_block: Option<BlockNumber>,
     ) {
-        self.msgs
-            .send(Feedback {
-                indexer,
-                deployment,
-                success,
-                latency_ms,
-                latest_block,
-            })
-            .unwrap();
+        let feedback = Feedback {
+            indexer,
+            deployment,
+            success,
+            latency_ms,
+            latest_block,
+        };
+        if let Some(outbox) = self.outbox.lock().as_mut() {
+            outbox.add(&feedback);
+        }
+        self.send(Msg::Feedback(feedback));
+    }
+
+    fn send(&self, msg: Msg) {
+        self.msgs.send(msg).unwrap();
+    }
+}
+
+/// The most recent successful response latencies of a deployment.
+#[derive(Default)]
+struct LatencySamples(VecDeque<u16>);
+
+impl LatencySamples {
+    const MAX_LEN: usize = 128;
+    const MIN_LEN: usize = 8;
+
+    fn push(&mut self, latency_ms: u16) {
+        if self.0.len() >= Self::MAX_LEN {
+            self.0.pop_front();
+        }
+        self.0.push_back(latency_ms);
+    }
+
+    fn percentile(&self, percentile: f64) -> Option<u16> {
+        if self.0.len() < Self::MIN_LEN {
+            return None;
+        }
+        let mut samples: Vec<u16> = self.0.iter().copied().collect();
+        samples.sort_unstable();
+        let index = ((samples.len() - 1) as f64 * percentile.clamp(0.0, 1.0)).round() as usize;
+        Some(samples[index])
     }
 }
 
 #[derive(Default)]
 struct DoubleBuffer([RwLock<HashMap<(IndexerId, DeploymentId), Snapshot>>; 2]);
 
+/// Number of feedback samples replayed to restore the performance of an indexing.
+const RESTORED_SAMPLES: u32 = 16;
+/// Maximum age, in seconds, applied as decay to the restored performance.
+const MAX_RESTORED_AGE_S: u64 = 60 * 60;
+
+/// The indexing performance, as stored to disk.
+///
+/// The performance isn't stored as is. For each indexing, the expected success rate and latency
+/// are stored, and the performance is restored by replaying feedback matching these. The restored
+/// performance is decayed once for each second since the cache was collected, like it would have
+/// been in memory.
+#[derive(Serialize, Deserialize)]
+pub struct IndexingPerformanceCache {
+    /// Milliseconds since Unix epoch, of when the cache was collected.
+    pub timestamp: u64,
+    pub indexings: Vec<CachedSnapshot>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CachedSnapshot {
+    pub indexer: IndexerId,
+    pub deployment: DeploymentId,
+    pub success_rate: f64,
+    pub latency_ms: u16,
+    pub latest_block: Option<BlockNumber>,
+}
+
+impl IndexingPerformanceCache {
+    fn collect(data: &HashMap<(IndexerId, DeploymentId), Snapshot>) -> Self {
+        let indexings = data
+            .iter()
+            .map(|((indexer, deployment), snapshot)| {
+                let expected = snapshot.response.expected_performance();
+                CachedSnapshot {
+                    indexer: *indexer,
+                    deployment: *deployment,
+                    success_rate: expected.success_rate.as_f64(),
+                    latency_ms: (expected.latency_ms() as u64).min(u16::MAX as u64) as u16,
+                    latest_block: snapshot.latest_block,
+                }
+            })
+            .collect();
+        Self {
+            timestamp: unix_timestamp(),
+            indexings,
+        }
+    }
+
+    fn restore(&self, age_s: u64) -> HashMap<(IndexerId, DeploymentId), Snapshot> {
+        // The replayed feedback only depends on the number of successes and the latency. So it's
+        // only replayed once for the indexings sharing them.
+        let mut replayed: HashMap<(u32, u16), indexer_selection::Performance> = HashMap::new();
+        self.indexings
+            .iter()
+            .map(|cached| {
+                let successes =
+                    (cached.success_rate.clamp(0.0, 1.0) * RESTORED_SAMPLES as f64).round() as u32;
+                let response = replayed
+                    .entry((successes, cached.latency_ms))
+                    .or_insert_with(|| {
+                        let mut response = indexer_selection::Performance::default();
+                        for n in 0..RESTORED_SAMPLES {
+                            response.feedback(n < successes, cached.latency_ms);
+                        }
+                        for _ in 0..age_s.min(MAX_RESTORED_AGE_S) {
+                            response.decay();
+                        }
+                        response
+                    })
+                    .clone();
+                let snapshot = Snapshot {
+                    response,
+                    latest_block: cached.latest_block,
+                    last_feedback: None,
+                };
+                ((cached.indexer, cached.deployment), snapshot)
+            })
+            .collect()
+    }
+}
+
+/// Load the indexing performance cache from the given file.
+pub fn load(path: &Path) -> anyhow::Result<IndexingPerformanceCache> {
+    let content = fs::read(path).context("read indexing performance cache")?;
+    serde_json::from_slice(&content).context("parse indexing performance cache")
+}
+
+/// Store the indexing performance cache to the given file.
+///
+/// The cache is written to a temporary file first, and then moved into place. So a partially
+/// written cache is never loaded.
+pub fn store(path: &Path, cache: &IndexingPerformanceCache) -> anyhow::Result<()> {
+    let content = serde_json::to_vec(cache).context("serialize indexing performance cache")?;
+    let tmp_path = path.with_extension("tmp");
+    fs::write(&tmp_path, content).context("write indexing performance cache")?;
+    fs::rename(&tmp_path, path).context("move indexing performance cache")?;
+    Ok(())
+}
+
 struct Actor {
     data: &'static DoubleBuffer,
+    latencies: &'static RwLock<HashMap<DeploymentId, LatencySamples>>,
 }
 
 impl Actor {
     fn spawn(
         data: &'static DoubleBuffer,
-        mut messages: mpsc::UnboundedReceiver<Feedback>,
+        latencies: &'static RwLock<HashMap<DeploymentId, LatencySamples>>,
+        mut messages: mpsc::UnboundedReceiver<Msg>,
         mut network: NetworkService,
+        cache_path: Option<PathBuf>,
     ) {
-        let mut actor = Self { data };
+        let mut actor = Self { data, latencies };
         let mut timer = tokio::time::interval(Duration::from_secs(1));
         timer.set_missed_tick_behavior(MissedTickBehavior::Skip);
+        let mut store_timer = tokio::time::interval(Duration::from_secs(60));
+        store_timer.set_missed_tick_behavior(MissedTickBehavior::Skip);
         tokio::spawn(async move {
             let batch_limit = 32;
             let mut msg_buf = Vec::with_capacity(batch_limit);
@@ -91,11 +294,23 @@ impl Actor {
                     _ = timer.tick() => actor.decay(),
                     _ = messages.recv_many(&mut msg_buf, batch_limit) => actor.handle_msgs(&mut msg_buf),
                     _ = network.changed() => actor.handle_network(&network),
+                    _ = store_timer.tick(), if cache_path.is_some() => {
+                        actor.store(cache_path.clone().unwrap());
+                    },
                 }
             }
         });
     }
 
+    fn store(&self, path: PathBuf) {
+        let cache = IndexingPerformanceCache::collect(&self.data.0[0].read());
+        tokio::task::spawn_blocking(move || {
+            if let Err(indexing_perf_store_err) = store(&path, &cache) {
+                tracing::error!(indexing_perf_store_err = format!("{indexing_perf_store_err:#}"));
+            }
+        });
+    }
+
     fn decay(&mut self) {
         for unlocked in &self.data.0 {
             for snapshot in unlocked.write().values_mut() {
@@ -104,27 +319,54 @@ impl Actor {
         }
     }
 
-    fn handle_msgs(&mut self, msgs: &mut Vec<Feedback>) {
+    fn handle_msgs(&mut self, msgs: &mut Vec<Msg>) {
+        let now = Instant::now();
+        {
+            let mut latencies = self.latencies.write();
+            for msg in msgs.iter() {
+                if let Msg::Feedback(feedback) = msg {
+                    if feedback.success {
+                        latencies
+                            .entry(feedback.deployment)
+                            .or_default()
+                            .push(feedback.latency_ms);
+                    }
+                }
+            }
+        }
         for unlocked in &self.data.0 {
             let mut locked = unlocked.write();
-            for Feedback {
-                indexer,
-                deployment,
-                success,
-                latency_ms,
-                latest_block,
-            } in msgs.drain(..)
-            {
-                let snapshot = locked.entry((indexer, deployment)).or_default();
-                snapshot.response.feedback(success, latency_ms);
-                snapshot.latest_block = match (snapshot.latest_block, latest_block) {
-                    (None, block) => block,
-                    (Some(a), Some(b)) if b > a => Some(b),
-                    (Some(a), _) => Some(a),
-                };
+            for msg in msgs.iter() {
+                match msg {
+                    Msg::Feedback(Feedback {
+                        indexer,
+                        deployment,
+                        success,
+                        latency_ms,
+                        latest_block,
+                    }) => {
+                        let snapshot = locked.entry((*indexer, *deployment)).or_default();
+                        snapshot.response.feedback(*success, *latency_ms);
+                        snapshot.last_feedback = Some(now);
+                        snapshot.latest_block = match (snapshot.latest_block, *latest_block) {
+                            (None, block) => block,
+                            (Some(a), Some(b)) if b > a => Some(b),
+                            (Some(a), _) => Some(a),
+                        };
+                    }
+                    Msg::RemoteFeedback {
+                        indexer,
+                        deployment,
+                        success,
+                        latency_ms,
+                    } => {
+                        let snapshot = locked.entry((*indexer, *deployment)).or_default();
+                        snapshot.response.feedback(*success, *latency_ms);
+                    }
+                }
             }
         }
-        debug_assert!(msgs.is_empty());
+        msgs.clear();
     }
 
     fn handle_network(&mut self, network: &NetworkService) {
@@ -142,3 +384,73 @@ impl Actor {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use thegraph_core::{deployment_id, Address};
+
+    use super::*;
+
+    #[test]
+    fn latency_percentile() {
+        //* Given
+        let mut samples = LatencySamples::default();
+        for latency_ms in 1..=4 {
+            samples.push(latency_ms);
+        }
+        assert_eq!(samples.percentile(0.9), None);
+
+        //* When
+        for latency_ms in 5..=(LatencySamples::MAX_LEN as u16 + 100) {
+            samples.push(latency_ms);
+        }
+
+        //* Then
+        assert_eq!(samples.0.len(), LatencySamples::MAX_LEN);
+        assert_eq!(samples.percentile(0.0), Some(101));
+        assert_eq!(
+            samples.percentile(1.0),
+            Some(LatencySamples::MAX_LEN as u16 + 100)
+        );
+        assert_eq!(samples.percentile(0.5), Some(165));
+    }
+
+    #[test]
+    fn store_and_restore_indexing_performance() {
+        //* Given
+        let indexer = IndexerId::from(Address::ZERO);
+        let deployment = deployment_id!("QmeYTH2fK2wv96XvnCGH2eyKFE8kmRfo53zYVy5dKysZtH");
+        let cache = IndexingPerformanceCache {
+            timestamp: unix_timestamp(),
+            indexings: vec![CachedSnapshot {
+                indexer,
+                deployment,
+                success_rate: 1.0,
+                latency_ms: 200,
+                latest_block: Some(100),
+            }],
+        };
+        let path = std::env::temp_dir().join(format!("indexing-perf-{}.json", std::process::id()));
+
+        //* When
+        store(&path, &cache).expect("failed to store indexing performance cache");
+        let loaded = load(&path);
+        let _ = fs::remove_file(&path);
+
+        //* Then
+        let restored = loaded
+            .expect("failed to load indexing performance cache")
+            .restore(0);
+        let snapshot = restored.get(&(indexer, deployment)).unwrap();
+        assert_eq!(snapshot.latest_block, Some(100));
+        assert_eq!(snapshot.last_feedback, None);
+        assert!(
+            snapshot
+                .response
+                .expected_performance()
+                .success_rate
+                .as_f64()
+                > 0.5
+        );
+    }
+}
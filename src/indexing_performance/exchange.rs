@@ -0,0 +1,243 @@
+//! Exchange of indexing performance between gateway replicas.
+//!
+//! Each replica periodically publishes a summary of the feedback it observed locally since the
+//! last publish, to a Kafka topic shared by the replicas. The summaries published by the other
+//! replicas are merged into the local indexing performance, by replaying a fraction of their
+//! feedback (`remote_weight`). So the local observations still dominate. Remote feedback only
+//! updates the expected response performance of indexings. It doesn't affect the local latency
+//! samples used for hedging, the latest blocks, or when indexings were last queried.
+
+use std::{collections::HashMap, time::Duration};
+
+use anyhow::Context as _;
+use rand::{rngs::SmallRng, Rng as _, SeedableRng as _};
+use rdkafka::{
+    consumer::{Consumer as _, StreamConsumer},
+    producer::{BaseRecord, DefaultProducerContext, NoCustomPartitioner, ThreadedProducer},
+    ClientConfig, Message as _,
+};
+use serde::{Deserialize, Serialize};
+use thegraph_core::{DeploymentId, IndexerId};
+use tokio::time::{interval, MissedTickBehavior};
+
+use super::{Feedback, IndexingPerformance, Msg};
+
+/// Maximum number of feedback samples replayed for a single remote indexing summary.
+const MAX_MERGED_SAMPLES: u32 = 64;
+
+/// Local feedback, accumulated since the last publish.
+#[derive(Default)]
+pub(super) struct Outbox(HashMap<(IndexerId, DeploymentId), Summary>);
+
+#[derive(Default)]
+struct Summary {
+    successes: u32,
+    failures: u32,
+    /// Sum of the latencies of successful responses
+    latency_ms_sum: u64,
+}
+
+impl Outbox {
+    pub(super) fn add(&mut self, feedback: &Feedback) {
+        let summary = self
+            .0
+            .entry((feedback.indexer, feedback.deployment))
+            .or_default();
+        if feedback.success {
+            summary.successes += 1;
+            summary.latency_ms_sum += feedback.latency_ms as u64;
+        } else {
+            summary.failures += 1;
+        }
+    }
+
+    fn take_message(&mut self, sender: &str) -> Message {
+        let indexings = self
+            .0
+            .drain()
+            .map(|((indexer, deployment), summary)| {
+                let samples = summary.successes.max(1) as u64;
+                IndexingSummary {
+                    indexer,
+                    deployment,
+                    successes: summary.successes,
+                    failures: summary.failures,
+                    latency_ms: (summary.latency_ms_sum / samples).min(u16::MAX as u64) as u16,
+                }
+            })
+            .collect();
+        Message {
+            sender: sender.to_string(),
+            indexings,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Message {
+    /// Identifies the publishing replica, so it can ignore its own messages
+    sender: String,
+    indexings: Vec<IndexingSummary>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct IndexingSummary {
+    indexer: IndexerId,
+    deployment: DeploymentId,
+    successes: u32,
+    failures: u32,
+    /// Mean latency of successful responses
+    latency_ms: u16,
+}
+
+impl IndexingPerformance {
+    /// Publish the local feedback to the Kafka topic every `publish_interval`, and merge the
+    /// feedback published by other replicas, weighted by `remote_weight` (in the range [0, 1]).
+    pub fn share(
+        &self,
+        kafka_config: impl Into<ClientConfig>,
+        topic: String,
+        publish_interval: Duration,
+        remote_weight: f64,
+    ) -> anyhow::Result<()> {
+        let sender = format!("{:016x}", rand::random::<u64>());
+        let kafka_config: ClientConfig = kafka_config.into();
+        let producer: ThreadedProducer<DefaultProducerContext, NoCustomPartitioner> =
+            kafka_config.create().context("kafka producer error")?;
+        let consumer: StreamConsumer = kafka_config
+            .clone()
+            .set("group.id", format!("gateway-indexing-performance-{sender}"))
+            .set("enable.auto.commit", "false")
+            .set("auto.offset.reset", "latest")
+            .create()
+            .context("kafka consumer error")?;
+        consumer
+            .subscribe(&[&topic])
+            .context("kafka consumer subscribe")?;
+        *self.outbox.lock() = Some(Outbox::default());
+
+        let this = self.clone();
+        let publish_topic = topic.clone();
+        let publish_sender = sender.clone();
+        tokio::spawn(async move {
+            let mut interval = interval(publish_interval);
+            interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            loop {
+                interval.tick().await;
+                let message = match this.outbox.lock().as_mut() {
+                    Some(outbox) => outbox.take_message(&publish_sender),
+                    None => continue,
+                };
+                if message.indexings.is_empty() {
+                    continue;
+                }
+                let payload = serde_json::to_vec(&message).unwrap();
+                let record: BaseRecord<(), [u8], ()> =
+                    BaseRecord::to(&publish_topic).payload(&payload);
+                if let Err((indexing_perf_publish_err, _)) = producer.send(record) {
+                    tracing::error!(%indexing_perf_publish_err);
+                }
+            }
+        });
+
+        let this = self.clone();
+        tokio::spawn(async move {
+            let mut rng = SmallRng::from_entropy();
+            loop {
+                let msg = match consumer.recv().await {
+                    Ok(msg) => msg,
+                    Err(indexing_perf_consume_err) => {
+                        tracing::warn!(%indexing_perf_consume_err);
+                        continue;
+                    }
+                };
+                let message: Message = match msg.payload().map(serde_json::from_slice) {
+                    Some(Ok(message)) => message,
+                    Some(Err(indexing_perf_decode_err)) => {
+                        tracing::warn!(%indexing_perf_decode_err);
+                        continue;
+                    }
+                    None => continue,
+                };
+                if message.sender == sender {
+                    continue;
+                }
+                for summary in message.indexings {
+                    this.merge(&summary, remote_weight, &mut rng);
+                }
+            }
+        });
+
+        Ok(())
+    }
+
+    /// Replay a fraction of the remote feedback, without adding it to the outbox.
+    fn merge(&self, summary: &IndexingSummary, weight: f64, rng: &mut SmallRng) {
+        let successes = scaled_samples(summary.successes, weight, rng);
+        let failures = scaled_samples(summary.failures, weight, rng);
+        for n in 0..(successes + failures) {
+            self.send(Msg::RemoteFeedback {
+                indexer: summary.indexer,
+                deployment: summary.deployment,
+                success: n < successes,
+                latency_ms: summary.latency_ms,
+            });
+        }
+    }
+}
+
+/// Scale the number of samples by the weight, rounding randomly so that the expected number of
+/// samples is preserved.
+fn scaled_samples(samples: u32, weight: f64, rng: &mut SmallRng) -> u32 {
+    let scaled = samples as f64 * weight.clamp(0.0, 1.0);
+    let mut result = scaled.floor() as u32;
+    if rng.gen::<f64>() < scaled.fract() {
+        result += 1;
+    }
+    result.min(MAX_MERGED_SAMPLES)
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use thegraph_core::{deployment_id, Address};
+
+    use super::*;
+
+    #[test]
+    fn outbox_summarizes_local_feedback() {
+        //* Given
+        let mut outbox = Outbox::default();
+        let indexer = IndexerId::from(Address::ZERO);
+        let deployment = deployment_id!("QmeYTH2fK2wv96XvnCGH2eyKFE8kmRfo53zYVy5dKysZtH");
+        let feedback = |success: bool, latency_ms: u16| Feedback {
+            indexer,
+            deployment,
+            success,
+            latency_ms,
+            latest_block: None,
+        };
+
+        //* When
+        outbox.add(&feedback(true, 100));
+        outbox.add(&feedback(true, 200));
+        outbox.add(&feedback(false, 300));
+        let message = outbox.take_message("a");
+
+        //* Then
+        assert!(outbox.0.is_empty());
+        assert_eq!(message.indexings.len(), 1);
+        let summary = &message.indexings[0];
+        assert_eq!((summary.successes, summary.failures), (2, 1));
+        assert_eq!(summary.latency_ms, 150);
+    }
+
+    #[test]
+    fn remote_samples_are_weighted() {
+        let mut rng = SmallRng::seed_from_u64(0);
+        assert_eq!(scaled_samples(10, 0.0, &mut rng), 0);
+        assert_eq!(scaled_samples(10, 0.5, &mut rng), 5);
+        assert_eq!(scaled_samples(10, 1.0, &mut rng), 10);
+        assert_eq!(scaled_samples(1000, 1.0, &mut rng), MAX_MERGED_SAMPLES);
+    }
+}
@@ -1,5 +1,5 @@
 use std::{
-    collections::HashSet,
+    collections::{BTreeMap, HashMap, HashSet},
     env,
     io::Write as _,
     net::{IpAddr, Ipv4Addr, SocketAddr},
@@ -20,27 +20,31 @@ use axum::{
 };
 use ethers::signers::{Signer, Wallet};
 use graph_gateway::{
-    auth::AuthContext,
+    admin,
+    auth::{APIKey, AuthContext},
     budgets::{Budgeter, USD},
-    chains::Chains,
-    client_query::{self, context::Context},
-    config::{self, ApiKeys, ExchangeRateProvider},
+    chains::{self, Chains},
+    client_query::{
+        self, circuit_breaker::CircuitBreaker, context::Context, response_cache::ResponseCache,
+    },
+    config::{self, ApiKeys, Config, EscrowConfig, ExchangeRateProvider, ReportSinkConfig},
     exchange_rate,
     indexer_client::IndexerClient,
     indexing_performance::IndexingPerformance,
     json,
     middleware::{
-        legacy_auth_adapter, RequestTracingLayer, RequireAuthorizationLayer, SetRequestIdLayer,
+        legacy_auth_adapter, query_settings, RequestTracingLayer, RequireAuthorizationLayer,
+        SetRequestIdLayer,
     },
-    network::{self, subgraph_client::Client as SubgraphClient},
-    receipts::ReceiptSigner,
+    network::{self, subgraph_client::Client as SubgraphClient, NetworkSettings},
+    receipts::{escrow::Escrow, ledger::ReceiptLedger, ReceiptSigner, SignerKeys},
     reports, subgraph_studio, vouchers,
 };
 use prometheus::{self, Encoder as _};
 use secp256k1::SecretKey;
 use serde_json::json;
 use simple_rate_limiter::RateLimiter;
-use thegraph_core::{attestation, Address, ChainId};
+use thegraph_core::{attestation, Address, ChainId, IndexerId};
 use tokio::{
     net::TcpListener,
     signal::unix::SignalKind,
@@ -61,6 +65,7 @@ async fn main() {
         .parse::<PathBuf>()
         .unwrap();
     let conf = config::load_from_file(&conf_path).expect("Failed to load config");
+    let startup_conf = conf.clone();
 
     let signer_address = Wallet::from_bytes(conf.receipts.signer.0.as_ref())
         .expect("failed to prepare receipt wallet");
@@ -77,6 +82,12 @@ async fn main() {
         .build()
         .unwrap();
 
+    let (network_settings_tx, network_settings_rx) = watch::channel(network_settings(&conf));
+    let (chain_aliases_tx, chain_aliases_rx) = watch::channel(conf.chain_aliases.clone());
+    let (query_fees_target_tx, query_fees_target_rx) = watch::channel(USD(conf.query_fees_target));
+    let (signer_keys_tx, signer_keys_rx) =
+        watch::channel(signer_keys(&conf.receipts.signer.0, &conf.receipts));
+
     let grt_per_usd = match conf.exchange_rate_provider {
         ExchangeRateProvider::Fixed(grt_per_usd) => watch::channel(grt_per_usd).1,
         ExchangeRateProvider::Rpc(url) => exchange_rate::grt_per_usd(url).await.unwrap(),
@@ -109,13 +120,22 @@ async fn main() {
     let mut network = network::service::spawn(
         http_client.clone(),
         network_subgraph_client,
-        conf.min_indexer_version,
-        conf.min_graph_node_version,
-        conf.blocked_indexers,
         indexer_host_blocklist,
-        conf.poi_blocklist,
+        network_settings_rx,
+        conf.network_cache.clone(),
     );
-    let indexing_perf = IndexingPerformance::new(network.clone());
+    let indexing_perf =
+        IndexingPerformance::new(network.clone(), conf.indexing_performance_cache.clone());
+    if let Some(exchange_conf) = conf.indexing_performance_exchange.clone() {
+        indexing_perf
+            .share(
+                conf.kafka.clone(),
+                exchange_conf.topic,
+                Duration::from_secs(exchange_conf.publish_interval_secs),
+                exchange_conf.remote_weight,
+            )
+            .expect("failed to share indexing performance");
+    }
     network.wait_until_ready().await;
 
     let legacy_signer: &'static SecretKey = Box::leak(Box::new(
@@ -124,63 +144,168 @@ async fn main() {
             .map(|s| s.0)
             .unwrap_or(conf.receipts.signer.0),
     ));
+    let receipt_ledger: Option<&'static ReceiptLedger> = conf.receipts.ledger.map(|ledger| {
+        let ledger = ReceiptLedger::open(
+            ledger.path,
+            ledger.max_segment_bytes.unwrap_or(100 * (1 << 20)),
+        )
+        .expect("failed to open receipt ledger");
+        &*Box::leak(Box::new(ledger))
+    });
+    let escrow: Option<&'static Escrow> =
+        conf.receipts.escrow.map(|escrow_conf| match escrow_conf {
+            EscrowConfig::Fixed { balances } => {
+                let balances = balances
+                    .into_iter()
+                    .map(|(indexer, balance)| (IndexerId::from(indexer), balance))
+                    .collect();
+                &*Box::leak(Box::new(Escrow::new(balances)))
+            }
+            EscrowConfig::Subgraph {
+                indexers,
+                sender,
+                poll_interval_secs,
+                allow_unknown_balances,
+            } => {
+                let escrow: &'static Escrow =
+                    Box::leak(Box::new(Escrow::unknown(allow_unknown_balances)));
+                escrow.spawn_subgraph_poller(
+                    indexer_client.clone(),
+                    indexers,
+                    sender,
+                    Duration::from_secs(poll_interval_secs.unwrap_or(60)),
+                );
+                escrow
+            }
+        });
+    if let (Some(escrow), Some(ledger)) = (escrow, receipt_ledger) {
+        escrow.restore(&ledger.all_totals());
+    }
     let receipt_signer: &'static ReceiptSigner = Box::leak(Box::new(ReceiptSigner::new(
-        conf.receipts.signer.0,
+        &signer_keys_rx.borrow(),
         conf.receipts.chain_id,
         conf.receipts.verifier,
-        legacy_signer,
+        (!conf.receipts.legacy_disabled).then_some(legacy_signer),
+        receipt_ledger,
+        escrow,
     )));
+    receipt_signer.spawn_signer_updates(signer_keys_rx);
 
     // Initialize the auth service
-    let auth_service =
+    let (auth_service, api_keys_tx) =
         init_auth_service(http_client.clone(), conf.api_keys, conf.payment_required).await;
 
-    let budgeter: &'static Budgeter =
-        Box::leak(Box::new(Budgeter::new(USD(conf.query_fees_target))));
+    let budgeter: &'static Budgeter = Box::leak(Box::new(Budgeter::new(query_fees_target_rx)));
+
+    spawn_config_reloader(
+        conf_path,
+        startup_conf,
+        ConfigUpdates {
+            network: network_settings_tx,
+            chain_aliases: chain_aliases_tx,
+            query_fees_target: query_fees_target_tx,
+            api_keys: api_keys_tx,
+            signer_keys: signer_keys_tx,
+        },
+    );
 
+    let report_sink: Box<dyn reports::ReportSink> = match conf.reports {
+        ReportSinkConfig::Kafka => Box::new(reports::KafkaSink::new(conf.kafka).unwrap()),
+        ReportSinkConfig::File {
+            path,
+            max_file_bytes,
+        } => Box::new(
+            reports::FileSink::new(path, max_file_bytes.unwrap_or(100 * (1 << 20))).unwrap(),
+        ),
+        ReportSinkConfig::Stdout => Box::<reports::StdoutSink>::default(),
+    };
     let reporter = reports::Reporter::create(
         tap_signer,
         conf.graph_env_id,
-        conf.query_fees_target,
+        budgeter.query_fees_target.clone(),
         reports::Topics {
             client_request: "gateway_client_query_results",
             indexer_request: "gateway_indexer_attempts",
             attestation: "gateway_attestations",
             indexer_fees: "gateway_indexer_fees",
         },
-        conf.kafka,
-    )
-    .unwrap();
+        report_sink,
+    );
+
+    let response_cache = conf
+        .response_cache
+        .map(|cache_conf| -> &'static ResponseCache {
+            Box::leak(Box::new(ResponseCache::new(
+                Duration::from_secs(cache_conf.ttl_secs),
+                cache_conf.max_entries,
+                cache_conf.min_confirmations,
+            )))
+        });
+
+    let circuit_breaker = conf
+        .circuit_breaker
+        .map(|breaker_conf| -> &'static CircuitBreaker {
+            Box::leak(Box::new(CircuitBreaker::new(
+                breaker_conf.failure_threshold,
+                Duration::from_secs(breaker_conf.cooldown_secs),
+            )))
+        });
+
+    let chains: &'static Chains = Box::leak(Box::new(Chains::new(chain_aliases_rx)));
+    for (chain, rpc) in conf.chain_rpcs {
+        let poll_interval = Duration::from_secs(rpc.poll_interval_secs.unwrap_or(5));
+        chains::head_poller::spawn(
+            http_client.clone(),
+            chains.chain(&chain),
+            rpc.url,
+            poll_interval,
+        );
+    }
+
+    receipt_signer.spawn_legacy_pool_pruner(network.clone());
 
     let ctx = Context {
         indexer_client,
         receipt_signer,
         budgeter,
-        chains: Box::leak(Box::new(Chains::new(conf.chain_aliases))),
+        chains,
         grt_per_usd,
         indexing_perf,
         network,
         attestation_domain,
         reporter,
+        response_cache,
+        hedging: conf.hedging,
+        circuit_breaker,
+        escrow,
+        selection: Box::leak(Box::new(conf.selection)),
+        response_metadata_headers: conf.response_metadata_headers,
     };
 
-    // Host metrics on a separate server with a port that isn't open to public requests.
-    tokio::spawn(async move {
-        let router = Router::new().route("/metrics", routing::get(handle_metrics));
-
-        let metrics_listener = TcpListener::bind(SocketAddr::new(
-            IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)),
-            conf.port_metrics,
-        ))
-        .await
-        .expect("Failed to bind metrics server");
+    if let Some(probes_conf) = conf.health_probes {
+        client_query::health_prober::spawn(
+            ctx.clone(),
+            Duration::from_secs(probes_conf.interval_secs),
+            Duration::from_secs(probes_conf.max_idle_secs),
+            probes_conf.max_probes,
+            probes_conf.max_fee_usd,
+        );
+    }
 
-        axum::serve(metrics_listener, router.into_make_service())
-            // disable Nagle's algorithm
-            .tcp_nodelay(true)
-            .await
-            .expect("Failed to start metrics server");
-    });
+    // Host metrics and the admin API on separate servers with ports that aren't open to public
+    // requests. Unless configured otherwise, the admin API is served alongside the metrics.
+    let mut metrics_router = Router::new().route("/metrics", routing::get(handle_metrics));
+    let admin_router = admin::router(
+        ctx.network.clone(),
+        ctx.chains,
+        ctx.selection,
+        receipt_ledger,
+    );
+    match conf.port_admin {
+        Some(port_admin) => spawn_private_server("admin", port_admin, admin_router),
+        None => metrics_router = metrics_router.nest("/admin", admin_router),
+    };
+    spawn_private_server("metrics", conf.port_metrics, metrics_router);
 
     let rate_limiter_slots = 10;
     let rate_limiter: &'static RateLimiter<String> =
@@ -210,6 +335,14 @@ async fn main() {
             "/subgraphs/id/:subgraph_id",
             routing::post(client_query::handle_query),
         )
+        .route(
+            "/deployments/id/:deployment_id/dry-run",
+            routing::post(client_query::handle_dry_run),
+        )
+        .route(
+            "/subgraphs/id/:subgraph_id/dry-run",
+            routing::post(client_query::handle_dry_run),
+        )
         .route(
             "/:api_key/deployments/id/:deployment_id",
             routing::post(client_query::handle_query),
@@ -227,7 +360,8 @@ async fn main() {
                     CorsLayer::new()
                         .allow_origin(cors::Any)
                         .allow_headers(cors::Any)
-                        .allow_methods([http::Method::OPTIONS, http::Method::POST]),
+                        .allow_methods([http::Method::OPTIONS, http::Method::POST])
+                        .expose_headers(cors::Any),
                 )
                 // Set up the query tracing span
                 .layer(RequestTracingLayer)
@@ -236,7 +370,9 @@ async fn main() {
                 // Handle legacy in-path auth, and convert it into a header
                 .layer(middleware::from_fn(legacy_auth_adapter))
                 // Require the query to be authorized
-                .layer(RequireAuthorizationLayer::new(auth_service)),
+                .layer(RequireAuthorizationLayer::new(auth_service))
+                // Parse the client's query settings headers
+                .layer(middleware::from_fn(query_settings)),
         );
 
     let router = Router::new()
@@ -259,9 +395,15 @@ async fn main() {
             "/voucher",
             routing::post(vouchers::handle_voucher).with_state(legacy_signer),
         )
+        .route(
+            "/rav-request",
+            routing::post(vouchers::handle_rav_request)
+                .with_state(receipt_signer)
+                .layer(DefaultBodyLimit::max(3_000_000)),
+        )
         .route(
             "/budget",
-            routing::get(|| async { budgeter.query_fees_target.0.to_string() }),
+            routing::get(|| async { budgeter.query_fees_target.borrow().0.to_string() }),
         )
         .nest("/api", api)
         .layer(middleware::from_fn_with_state(rate_limiter, ip_rate_limit));
@@ -324,6 +466,21 @@ async fn ip_rate_limit(
     Ok(next.run(req).await)
 }
 
+fn spawn_private_server(name: &'static str, port: u16, router: Router) {
+    tokio::spawn(async move {
+        let listener =
+            TcpListener::bind(SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), port))
+                .await
+                .unwrap_or_else(|err| panic!("Failed to bind {name} server: {err}"));
+
+        axum::serve(listener, router.into_make_service())
+            // disable Nagle's algorithm
+            .tcp_nodelay(true)
+            .await
+            .unwrap_or_else(|err| panic!("Failed to start {name} server: {err}"));
+    });
+}
+
 async fn handle_metrics() -> impl axum::response::IntoResponse {
     let encoder = prometheus::TextEncoder::new();
     let metric_families = prometheus::gather();
@@ -361,31 +518,127 @@ pub fn init_logging(executable_name: &str, json: bool) {
 
 /// Creates a new [`AuthContext`] from the given configuration.
 ///
-/// This functions awaits the completion of the initial API keys fetch.
+/// This functions awaits the completion of the initial API keys fetch. If the API keys are fixed,
+/// the sender used to update them is also returned.
 async fn init_auth_service(
     http: reqwest::Client,
     config: Option<ApiKeys>,
     payment_required: bool,
-) -> AuthContext {
+) -> (AuthContext, Option<watch::Sender<HashMap<String, APIKey>>>) {
     let special_api_keys = match &config {
         Some(ApiKeys::Endpoint { special, .. }) => Arc::new(HashSet::from_iter(special.clone())),
         _ => Default::default(),
     };
 
-    let api_keys = match config {
+    let (api_keys, api_keys_tx) = match config {
         Some(ApiKeys::Endpoint { url, auth, .. }) => {
-            subgraph_studio::api_keys(http, url, auth.0).await
+            (subgraph_studio::api_keys(http, url, auth.0).await, None)
         }
         Some(ApiKeys::Fixed(api_keys)) => {
-            let api_keys = api_keys.into_iter().map(|k| (k.key.clone(), k)).collect();
-            watch::channel(api_keys).1
+            let (tx, rx) = watch::channel(fixed_api_keys(api_keys));
+            (rx, Some(tx))
         }
-        None => watch::channel(Default::default()).1,
+        None => (watch::channel(Default::default()).1, None),
     };
 
-    AuthContext {
+    let ctx = AuthContext {
         payment_required,
         api_keys,
         special_api_keys,
+        rate_limiter: Default::default(),
+    };
+    (ctx, api_keys_tx)
+}
+
+fn fixed_api_keys(api_keys: Vec<APIKey>) -> HashMap<String, APIKey> {
+    api_keys.into_iter().map(|k| (k.key.clone(), k)).collect()
+}
+
+fn network_settings(conf: &Config) -> NetworkSettings {
+    NetworkSettings {
+        min_indexer_service_version: conf.min_indexer_version.clone(),
+        min_graph_node_version: conf.min_graph_node_version.clone(),
+        indexer_blocklist: conf.blocked_indexers.clone(),
+        poi_blocklist: conf.poi_blocklist.clone(),
+    }
+}
+
+/// The TAP signer keys, rotating from `signer` to the keys of the `receipts` signer rotation.
+fn signer_keys(signer: &SecretKey, receipts: &config::Receipts) -> SignerKeys {
+    let rotation = receipts
+        .signer_rotation
+        .iter()
+        .map(|rotated| (rotated.key.0, rotated.active_from));
+    SignerKeys {
+        keys: [(*signer, 0)].into_iter().chain(rotation).collect(),
+        grace_period: Duration::from_secs(receipts.signer_grace_period_secs.unwrap_or(604800)),
+    }
+}
+
+/// Senders for the configuration values that can be updated without restarting the gateway.
+struct ConfigUpdates {
+    network: watch::Sender<NetworkSettings>,
+    chain_aliases: watch::Sender<BTreeMap<String, String>>,
+    query_fees_target: watch::Sender<USD>,
+    /// Only set when using a fixed set of API keys.
+    api_keys: Option<watch::Sender<HashMap<String, APIKey>>>,
+    signer_keys: watch::Sender<SignerKeys>,
+}
+
+/// Reload the configuration file on SIGHUP, and apply the changes that can be made without
+/// restarting the gateway. Changes to any other fields are logged and ignored.
+fn spawn_config_reloader(path: PathBuf, startup_conf: Config, updates: ConfigUpdates) {
+    fn update<T: PartialEq>(tx: &watch::Sender<T>, value: T) -> bool {
+        tx.send_if_modified(|current| {
+            if *current == value {
+                return false;
+            }
+            *current = value;
+            true
+        })
     }
+
+    tokio::spawn(async move {
+        let mut sighup =
+            tokio::signal::unix::signal(SignalKind::hangup()).expect("install SIGHUP handler");
+        while sighup.recv().await.is_some() {
+            let conf = match config::load_from_file(&path) {
+                Ok(conf) => conf,
+                Err(config_reload_err) => {
+                    tracing::error!(%config_reload_err);
+                    continue;
+                }
+            };
+
+            let restart_required = startup_conf.restart_required(&conf);
+            if !restart_required.is_empty() {
+                tracing::warn!(
+                    fields = ?restart_required,
+                    "config changes require a restart, ignoring them"
+                );
+            }
+
+            let mut updated: Vec<&'static str> = Vec::new();
+            if update(&updates.network, network_settings(&conf)) {
+                updated.push("network");
+            }
+            if update(&updates.chain_aliases, conf.chain_aliases) {
+                updated.push("chain_aliases");
+            }
+            if update(&updates.query_fees_target, USD(conf.query_fees_target)) {
+                updated.push("query_fees_target");
+            }
+            if let (Some(tx), Some(ApiKeys::Fixed(api_keys))) = (&updates.api_keys, conf.api_keys) {
+                if update(tx, fixed_api_keys(api_keys)) {
+                    updated.push("api_keys");
+                }
+            }
+            // The initial signer is the gateway ID, so changing it requires a restart.
+            let signers = signer_keys(&startup_conf.receipts.signer.0, &conf.receipts);
+            if update(&updates.signer_keys, signers) {
+                updated.push("receipts.signer_rotation");
+            }
+            tracing::info!(?updated, "config reloaded");
+        }
+    });
 }
@@ -1,9 +1,10 @@
 use lazy_static::lazy_static;
 use prometheus::{
     core::{MetricVec, MetricVecBuilder},
-    register_gauge, register_histogram, register_histogram_vec, register_int_counter,
-    register_int_counter_vec, register_int_gauge_vec, Gauge, Histogram, HistogramTimer,
-    HistogramVec, IntCounter, IntCounterVec, IntGaugeVec,
+    register_counter, register_gauge, register_gauge_vec, register_histogram,
+    register_histogram_vec, register_int_counter, register_int_counter_vec, register_int_gauge,
+    register_int_gauge_vec, Counter, Gauge, GaugeVec, Histogram, HistogramTimer, HistogramVec,
+    IntCounter, IntCounterVec, IntGauge, IntGaugeVec,
 };
 
 lazy_static! {
@@ -17,7 +18,17 @@ pub struct Metrics {
     pub collect_receipts: ResponseMetrics,
     pub partial_voucher: ResponseMetrics,
     pub voucher: ResponseMetrics,
+    pub rav_request: ResponseMetrics,
     pub blocks_per_minute: IntGaugeVec,
+    pub chain_reorgs: IntCounterVec,
+    pub chain_reorg_depth: HistogramVec,
+    pub response_cache_hits: IntCounter,
+    pub indexing_performance_restored: IntGauge,
+    pub escrow_balance: GaugeVec,
+    pub escrow_outstanding: GaugeVec,
+    pub escrow_insufficient: IntCounterVec,
+    pub escrow_unknown: IntCounter,
+    pub health_probe_fees: Counter,
 }
 
 impl Metrics {
@@ -40,12 +51,64 @@ impl Metrics {
             ),
             partial_voucher: ResponseMetrics::new("gw_partial_voucher", "partial-voucher request"),
             voucher: ResponseMetrics::new("gw_voucher", "requests for voucher"),
+            rav_request: ResponseMetrics::new("gw_rav_request", "TAP RAV request"),
             blocks_per_minute: register_int_gauge_vec!(
                 "gw_blocks_per_minute",
                 "chain blocks per minute",
                 &["chain"]
             )
             .unwrap(),
+            chain_reorgs: register_int_counter_vec!(
+                "gw_chain_reorgs",
+                "chain reorgs detected",
+                &["chain"]
+            )
+            .unwrap(),
+            chain_reorg_depth: register_histogram_vec!(
+                "gw_chain_reorg_depth",
+                "number of blocks orphaned by chain reorgs",
+                &["chain"],
+                vec![1.0, 2.0, 4.0, 8.0, 16.0, 32.0, 64.0]
+            )
+            .unwrap(),
+            response_cache_hits: register_int_counter!(
+                "gw_response_cache_hits",
+                "client queries served from the response cache"
+            )
+            .unwrap(),
+            indexing_performance_restored: register_int_gauge!(
+                "gw_indexing_performance_restored",
+                "indexings with performance restored from the cache at startup"
+            )
+            .unwrap(),
+            escrow_balance: register_gauge_vec!(
+                "gw_escrow_balance",
+                "escrow balance available to each indexer, in GRT",
+                &["indexer"]
+            )
+            .unwrap(),
+            escrow_outstanding: register_gauge_vec!(
+                "gw_escrow_outstanding",
+                "value of the unaggregated TAP receipts issued to each indexer, in GRT",
+                &["indexer"]
+            )
+            .unwrap(),
+            escrow_insufficient: register_int_counter_vec!(
+                "gw_escrow_insufficient",
+                "indexers skipped for selection due to insufficient escrow",
+                &["indexer"]
+            )
+            .unwrap(),
+            escrow_unknown: register_int_counter!(
+                "gw_escrow_unknown",
+                "escrow checks without known escrow balances"
+            )
+            .unwrap(),
+            health_probe_fees: register_counter!(
+                "gw_health_probe_fees",
+                "indexer fees paid for health probes, in GRT"
+            )
+            .unwrap(),
         }
     }
 }
@@ -1,9 +1,11 @@
 mod legacy_auth;
+mod query_settings;
 mod request_id;
 mod request_tracing;
 mod require_auth;
 
 pub use legacy_auth::legacy_auth_adapter;
+pub use query_settings::query_settings;
 pub use request_id::{RequestId, SetRequestId, SetRequestIdLayer};
 pub use request_tracing::{RequestTracing, RequestTracingLayer};
 pub use require_auth::{RequireAuthorization, RequireAuthorizationLayer};
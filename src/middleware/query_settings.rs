@@ -0,0 +1,23 @@
+use axum::{
+    body::Body, extract::Request, http::Response, middleware::Next, response::IntoResponse as _,
+};
+
+use crate::{auth::AuthSettings, client_query::query_settings::QuerySettings, graphql};
+
+/// This middleware parses the client's query settings headers into a `QuerySettings` extension,
+/// checked against the limits of the request's `AuthSettings`.
+///
+/// If the request has no `AuthSettings` extension, it is left unchanged. If the headers are
+/// invalid, the middleware returns a GraphQL error response.
+pub async fn query_settings(mut request: Request, next: Next) -> Response<Body> {
+    if let Some(auth) = request.extensions().get::<AuthSettings>() {
+        match QuerySettings::from_headers(request.headers(), auth) {
+            Ok(settings) => {
+                request.extensions_mut().insert(settings);
+            }
+            Err(err) => return graphql::error_response(err).into_response(),
+        }
+    }
+
+    next.run(request).await
+}
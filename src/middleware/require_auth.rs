@@ -75,6 +75,8 @@ impl<F, R> ResponseFuture<F, R> {
 /// The request is not authorized if the `Authorization` header is not present or the bearer
 /// token is invalid, in this the middleware returns a GraphQL error response.
 ///
+/// The request is also rejected if it exceeds the rate limits of the API key.
+///
 /// Otherwise, the middleware forwards the request to the inner service inserting an `AuthSettings`
 /// extension into the request.
 ///
@@ -132,6 +134,11 @@ where
         };
         tracing::debug!(user = ?auth.user, api_key = %auth.key);
 
+        if let Err(err) = self.ctx.check_rate_limits(&auth) {
+            // If the API key rate limits are exceeded, return an error response
+            return ResponseFuture::error(graphql::error_response(Error::Auth(err)));
+        }
+
         // Insert the `AuthSettings` extension into the request
         req.extensions_mut().insert(auth);
 
@@ -179,13 +186,14 @@ mod tests {
     use tokio_test::assert_ready_ok;
 
     use super::{AuthContext, AuthSettings, RequireAuthorizationLayer};
-    use crate::auth::APIKey;
+    use crate::auth::{APIKey, RateLimits};
 
     fn test_auth_ctx(key: Option<&str>) -> AuthContext {
         let mut ctx = AuthContext {
             payment_required: false,
             api_keys: watch::channel(Default::default()).1,
             special_api_keys: Default::default(),
+            rate_limiter: Default::default(),
         };
         if let Some(key) = key {
             ctx.api_keys = watch::channel(HashMap::from([(
@@ -474,4 +482,48 @@ mod tests {
             assert_eq!(auth.key, "0123456789abcdef0123456789abcdef");
         });
     }
+
+    /// If the API key rate limits are exceeded, the middleware should return an error response.
+    #[tokio::test]
+    async fn api_key_rate_limit_exceeded() {
+        //* Given
+        let api_key = "0123456789abcdef0123456789abcdef";
+
+        let mut auth_ctx = test_auth_ctx(None);
+        auth_ctx.api_keys = watch::channel(HashMap::from([(
+            api_key.into(),
+            APIKey {
+                key: api_key.into(),
+                rate_limits: RateLimits {
+                    max_requests_per_second: None,
+                    max_requests_per_day: Some(1),
+                },
+                ..Default::default()
+            },
+        )]))
+        .1;
+
+        let (mut svc, mut handle) =
+            tower_test::mock::spawn_layer(RequireAuthorizationLayer::new(auth_ctx));
+
+        //* When
+        // The service must be ready before calling it
+        handle.allow(2);
+        assert_ready_ok!(svc.poll_ready());
+
+        // The first request is within the limits
+        svc.call(test_req_with_auth_header(api_key));
+
+        assert_ready_ok!(svc.poll_ready());
+        let res = svc.call(test_req_with_auth_header(api_key)).await;
+
+        //* Then
+        assert_matches!(res, Ok(mut res) => {
+            assert_eq!(res.status(), http::StatusCode::OK);
+            assert_matches!(deserialize_graphql_response_body::<()>(res.body_mut()).await, Ok(res_body) => {
+                assert_eq!(res_body.errors.len(), 1);
+                assert_eq!(res_body.errors[0].message, "auth error: daily request limit exceeded for this API key");
+            });
+        });
+    }
 }
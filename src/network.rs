@@ -2,12 +2,14 @@
 //! provides information about the subgraphs (and subgraph deployments) registered in the network
 //! smart contract, as well as the indexers that are indexing them.
 
+pub use config::NetworkSettings;
 pub use errors::{
     DeploymentError, IndexingError, ResolutionError, SubgraphError, UnavailableReason,
 };
-pub use internal::{Indexer, Indexing, IndexingId};
+pub use internal::{Indexer, Indexing, IndexingId, NetworkTopologySnapshot};
 pub use service::{NetworkService, ResolvedSubgraphInfo};
 
+mod cache;
 mod config;
 mod errors;
 pub mod indexer_host_resolver;
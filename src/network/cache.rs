@@ -0,0 +1,183 @@
+//! On-disk network cache, used to warm-start the network topology.
+//!
+//! The cache holds the network subgraph data and the indexers' information cached by the
+//! resolvers. It is written after every network topology update, and loaded at startup, so the
+//! gateway can serve queries using a stale network topology before the first update completes.
+
+use std::{collections::HashMap, fs, net::IpAddr, path::Path};
+
+use anyhow::Context as _;
+use semver::Version;
+use serde::{Deserialize, Serialize};
+use thegraph_core::DeploymentId;
+
+use super::{
+    indexer_indexing_progress_resolver::IndexingProgressInfo, internal::InternalState,
+    subgraph_client::types::Subgraph,
+};
+use crate::{indexers::cost_models::CostModelSource, time::unix_timestamp};
+
+#[derive(Serialize, Deserialize)]
+pub struct NetworkCache {
+    /// Milliseconds since Unix epoch, of when the cache was collected.
+    pub timestamp: u64,
+    /// The network subgraph data.
+    pub network_subgraph: Vec<Subgraph>,
+    /// The indexers' host addresses, keyed by host.
+    pub indexer_hosts: HashMap<String, Vec<IpAddr>>,
+    /// The indexers' "indexer service" versions, keyed by indexer URL.
+    pub indexer_service_versions: HashMap<String, Version>,
+    /// The indexers' "graph node" versions, keyed by indexer URL.
+    pub graph_node_versions: HashMap<String, Version>,
+    /// The indexings' progress, keyed by indexer URL.
+    pub indexing_progress: HashMap<String, Vec<(DeploymentId, IndexingProgressInfo)>>,
+    /// The indexings' cost model sources, keyed by indexer URL.
+    pub cost_models: HashMap<String, Vec<CostModelSource>>,
+}
+
+impl NetworkCache {
+    /// Collect the network cache from the network subgraph data and the resolvers' caches.
+    pub fn collect(network_subgraph: Vec<Subgraph>, state: &InternalState) -> Self {
+        let (indexer_service_versions, graph_node_versions) =
+            state.indexer_version_resolver.cached_entries();
+        let indexing_progress = state
+            .indexing_progress_resolver
+            .cached_entries()
+            .into_iter()
+            .map(|(url, progress)| (url, progress.into_iter().collect()))
+            .collect();
+        let mut cost_models: HashMap<String, Vec<CostModelSource>> = HashMap::new();
+        for ((url, _), source) in state.cost_model_resolver.cached_entries() {
+            cost_models
+                .entry(url)
+                .or_default()
+                .push(source.as_ref().clone());
+        }
+
+        Self {
+            timestamp: unix_timestamp(),
+            network_subgraph,
+            indexer_hosts: state.indexer_host_resolver.cached_entries(),
+            indexer_service_versions,
+            graph_node_versions,
+            indexing_progress,
+            cost_models,
+        }
+    }
+
+    /// Extend the resolvers' caches with the cached information.
+    ///
+    /// The indexer host addresses are not restored, the host resolver must resolve them again.
+    pub fn restore(&self, state: &InternalState) {
+        state.indexer_version_resolver.extend_cache(
+            self.indexer_service_versions.clone(),
+            self.graph_node_versions.clone(),
+        );
+        state.indexing_progress_resolver.extend_cache(
+            self.indexing_progress
+                .iter()
+                .map(|(url, progress)| (url.clone(), progress.iter().cloned().collect())),
+        );
+        state.cost_model_resolver.extend_cache(
+            self.cost_models
+                .iter()
+                .flat_map(|(url, sources)| sources.iter().map(|s| (url.clone(), s.clone()))),
+        );
+    }
+}
+
+/// Load the network cache from the given file.
+pub fn load(path: &Path) -> anyhow::Result<NetworkCache> {
+    let content = fs::read(path).context("read network cache")?;
+    serde_json::from_slice(&content).context("parse network cache")
+}
+
+/// Store the network cache to the given file.
+///
+/// The cache is written to a temporary file first, and then moved into place. So a partially
+/// written cache is never loaded.
+pub fn store(path: &Path, cache: &NetworkCache) -> anyhow::Result<()> {
+    let content = serde_json::to_vec(cache).context("serialize network cache")?;
+    let tmp_path = path.with_extension("tmp");
+    fs::write(&tmp_path, content).context("write network cache")?;
+    fs::rename(&tmp_path, path).context("move network cache")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn store_and_load_network_cache() {
+        //* Given
+        let network_subgraph = json!([
+          {
+            "id": "21dvLGCdpj4TNQXt7azhjc2sZhj2j5fWXuYCYG6z3mjP",
+            "versions": [
+              {
+                "version": 0,
+                "subgraphDeployment": {
+                  "ipfsHash": "QmaiXMTFDFPRKoXQceXwzuFYhAYDkUXHLmBVxLUQs4ZKsN",
+                  "manifest": {
+                    "network": "gnosis",
+                    "startBlock": "25313137"
+                  },
+                  "indexerAllocations": [
+                    {
+                      "id": "0x8de241c35f8bc02ae9ad635e273372dd083f6520",
+                      "allocatedTokens": "2000000000000000000",
+                      "indexer": {
+                        "id": "0xedca8740873152ff30a2696add66d1ab41882beb",
+                        "stakedTokens": "1581895764461196487409847",
+                        "url": "https://arbitrum.graph.pinax.network/"
+                      }
+                    }
+                  ]
+                }
+              }
+            ]
+          }
+        ]);
+        let cache = NetworkCache {
+            timestamp: unix_timestamp(),
+            network_subgraph: serde_json::from_value(network_subgraph)
+                .expect("invalid network subgraph data"),
+            indexer_hosts: Default::default(),
+            indexer_service_versions: [(
+                "https://arbitrum.graph.pinax.network/".to_string(),
+                Version::new(1, 0, 0),
+            )]
+            .into(),
+            graph_node_versions: Default::default(),
+            indexing_progress: Default::default(),
+            cost_models: Default::default(),
+        };
+
+        let path = std::env::temp_dir().join(format!("network-cache-{}.json", std::process::id()));
+
+        //* When
+        store(&path, &cache).expect("failed to store network cache");
+        let loaded = load(&path);
+        let _ = fs::remove_file(&path);
+
+        //* Then
+        let loaded = loaded.expect("failed to load network cache");
+        assert_eq!(loaded.timestamp, cache.timestamp);
+        assert_eq!(loaded.network_subgraph.len(), 1);
+        assert_eq!(loaded.network_subgraph[0].id, cache.network_subgraph[0].id);
+        assert_eq!(
+            loaded.network_subgraph[0].versions[0]
+                .subgraph_deployment
+                .allocations[0]
+                .allocated_tokens,
+            2000000000000000000
+        );
+        assert_eq!(
+            loaded.indexer_service_versions,
+            cache.indexer_service_versions
+        );
+    }
+}
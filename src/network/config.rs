@@ -1,4 +1,9 @@
+use std::collections::BTreeMap;
+
 use semver::Version;
+use thegraph_core::Address;
+
+use crate::{config::BlockedIndexer, indexers::public_poi::ProofOfIndexingInfo};
 
 /// The minimum version requirements for the indexer.
 #[derive(Debug, Clone)]
@@ -17,3 +22,16 @@ impl Default for VersionRequirements {
         }
     }
 }
+
+/// The network service settings that can be updated while the service is running.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NetworkSettings {
+    /// The minimum indexer service version.
+    pub min_indexer_service_version: Version,
+    /// The minimum graph node version.
+    pub min_graph_node_version: Version,
+    /// Indexers to block, either on all or on specific deployments.
+    pub indexer_blocklist: BTreeMap<Address, BlockedIndexer>,
+    /// Public POIs to block.
+    pub poi_blocklist: Vec<ProofOfIndexingInfo>,
+}
@@ -104,6 +104,9 @@ impl From<IndexingError> for ResolutionError {
                     IndexerInfoResolutionError::GraphNodeVersionBelowMin(..) => {
                         UnavailableReason::GraphNodeVersionBelowMin
                     }
+                    IndexerInfoResolutionError::NotCached => {
+                        UnavailableReason::IndexerResolutionError("indexer info not resolved yet")
+                    }
                 };
                 ResolutionError::Unavailable(reason)
             }
@@ -152,6 +155,10 @@ pub enum IndexerInfoResolutionError {
     GraphNodeVersionResolutionFailed(VersionResolutionError),
     #[error("graph node version {0} below the minimum required {1}")]
     GraphNodeVersionBelowMin(Version, Version),
+    /// The indexer information is not available in the network cache, used before the first
+    /// network topology update.
+    #[error("indexer info not cached")]
+    NotCached,
 }
 
 /// Error when processing the indexer's indexing information.
@@ -81,6 +81,15 @@ impl HostResolver {
         cache_write.insert(host.to_owned(), res);
     }
 
+    /// Returns the successfully resolved IP addresses held in the cache, keyed by host.
+    pub fn cached_entries(&self) -> HashMap<String, Vec<IpAddr>> {
+        let cache_read = self.cache.read();
+        cache_read
+            .iter()
+            .filter_map(|(host, res)| Some((host.clone(), res.as_ref().ok()?.clone())))
+            .collect()
+    }
+
     /// Resolve the IP address of the given URL.
     ///
     /// The URL is resolved to an IP address. The result is cached so that subsequent calls with the
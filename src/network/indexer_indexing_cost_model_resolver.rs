@@ -97,6 +97,28 @@ impl CostModelResolver {
         }
     }
 
+    /// Returns the cached cost model sources for the given deployments, without fetching them.
+    pub fn resolve_from_cache(
+        &self,
+        url: &Url,
+        indexings: &[DeploymentId],
+    ) -> HashMap<DeploymentId, Ptr<CostModelSource>> {
+        self.get_from_cache(url.as_str(), indexings)
+    }
+
+    /// Returns the cached cost model sources, keyed by indexer URL and deployment ID.
+    pub fn cached_entries(&self) -> HashMap<(String, DeploymentId), Ptr<CostModelSource>> {
+        self.cache.read().clone()
+    }
+
+    /// Extends the cache with the given cost model sources, keyed by indexer URL.
+    pub fn extend_cache(&self, entries: impl IntoIterator<Item = (String, CostModelSource)>) {
+        let mut write_cache = self.cache.write();
+        for (url, source) in entries {
+            write_cache.insert((url, source.deployment), Ptr::new(source));
+        }
+    }
+
     /// Resolves the indexing progress of the given deployments.
     ///
     /// If the request successfully returns the data, the cached data is updated and the new data is
@@ -3,6 +3,7 @@
 use std::{collections::HashMap, time::Duration};
 
 use parking_lot::{Mutex, RwLock};
+use serde::{Deserialize, Serialize};
 use thegraph_core::{BlockNumber, DeploymentId};
 use url::Url;
 
@@ -29,7 +30,7 @@ pub enum ResolutionError {
 }
 
 /// The indexing progress information of a deployment on a chain.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct IndexingProgressInfo {
     /// The chain the deployment is associated with.
     pub chain: String,
@@ -55,6 +56,35 @@ impl IndexingProgressResolver {
         }
     }
 
+    /// Returns the cached indexing progress of the indexer's deployments, without fetching it.
+    pub fn resolve_from_cache(&self, url: &Url) -> HashMap<DeploymentId, IndexingProgressInfo> {
+        let cache = self.cache.read();
+        cache
+            .get(url.as_str())
+            .map(|cache| cache.lock().clone())
+            .unwrap_or_default()
+    }
+
+    /// Returns the cached indexing progress information, keyed by indexer URL.
+    pub fn cached_entries(&self) -> HashMap<String, HashMap<DeploymentId, IndexingProgressInfo>> {
+        let cache = self.cache.read();
+        cache
+            .iter()
+            .map(|(url, cache)| (url.clone(), cache.lock().clone()))
+            .collect()
+    }
+
+    /// Extends the cache with the given indexing progress information, keyed by indexer URL.
+    pub fn extend_cache(
+        &self,
+        entries: impl IntoIterator<Item = (String, HashMap<DeploymentId, IndexingProgressInfo>)>,
+    ) {
+        let mut cache = self.cache.write();
+        for (url, progress) in entries {
+            cache.entry(url).or_default().get_mut().extend(progress);
+        }
+    }
+
     /// Fetches the indexing progress of the given deployments from the indexer's status URL.
     async fn fetch_indexing_progress(
         &self,
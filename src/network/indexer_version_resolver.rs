@@ -63,6 +63,41 @@ impl VersionResolver {
         }
     }
 
+    /// Returns the cached indexer service version for the given URL, without fetching it.
+    pub fn cached_indexer_service_version(&self, url: &Url) -> Option<Version> {
+        let cache = self.indexer_service_version_cache.read();
+        cache.get(url.as_str()).cloned()
+    }
+
+    /// Returns the cached indexer graph-node version for the given URL, without fetching it.
+    pub fn cached_graph_node_version(&self, url: &Url) -> Option<Version> {
+        let cache = self.graph_node_version_cache.read();
+        cache.get(url.as_str()).cloned()
+    }
+
+    /// Returns the cached indexer service and graph-node versions, keyed by indexer URL.
+    pub fn cached_entries(&self) -> (HashMap<String, Version>, HashMap<String, Version>) {
+        (
+            self.indexer_service_version_cache.read().clone(),
+            self.graph_node_version_cache.read().clone(),
+        )
+    }
+
+    /// Extends the cache with the given indexer service and graph-node versions, keyed by
+    /// indexer URL.
+    pub fn extend_cache(
+        &self,
+        indexer_service_versions: impl IntoIterator<Item = (String, Version)>,
+        graph_node_versions: impl IntoIterator<Item = (String, Version)>,
+    ) {
+        self.indexer_service_version_cache
+            .write()
+            .extend(indexer_service_versions);
+        self.graph_node_version_cache
+            .write()
+            .extend(graph_node_versions);
+    }
+
     /// Fetches the indexer service version from the given URL.
     async fn fetch_indexer_service_version(
         &self,
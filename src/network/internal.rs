@@ -1,4 +1,4 @@
-use std::{collections::HashMap, time::Duration};
+use std::{collections::HashMap, net::IpAddr, time::Duration};
 
 use thegraph_core::{DeploymentId, IndexerId, SubgraphId};
 
@@ -8,7 +8,10 @@ pub use self::{
     state::InternalState,
     subgraph_processing::{AllocationInfo, DeploymentInfo, SubgraphInfo, SubgraphVersionInfo},
 };
-use super::{subgraph_client::Client as SubgraphClient, DeploymentError, SubgraphError};
+use super::{
+    subgraph_client::{self, Client as SubgraphClient},
+    DeploymentError, SubgraphError,
+};
 
 mod indexer_processing;
 mod pre_processing;
@@ -30,42 +33,63 @@ pub async fn fetch_update(
     )
 }
 
+/// Construct the network topology information from the information cached by the resolvers (and
+/// the given indexer host addresses), without sending any requests to the indexers.
+///
+/// The returned snapshot is marked as stale.
+pub fn cached_update(
+    network: &PreprocessedNetworkInfo,
+    state: &InternalState,
+    indexer_hosts: &HashMap<String, Vec<IpAddr>>,
+) -> NetworkTopologySnapshot {
+    let indexers_info =
+        indexer_processing::process_info_from_cache(state, indexer_hosts, &network.indexers);
+    let mut snapshot = snapshot::new_from(
+        indexers_info,
+        network.subgraphs.clone(),
+        network.deployments.clone(),
+    );
+    snapshot.stale = true;
+    snapshot
+}
+
 pub struct PreprocessedNetworkInfo {
     subgraphs: HashMap<SubgraphId, Result<SubgraphInfo, SubgraphError>>,
     deployments: HashMap<DeploymentId, Result<DeploymentInfo, DeploymentError>>,
     indexers: HashMap<IndexerId, IndexerRawInfo>,
 }
 
-/// Fetch the subgraphs information from the graph network subgraph and performs pre-processing
-/// steps, i.e., validation and conversion into the internal representation.
-///
-///   1. Fetch the subgraphs information from the graph network subgraph.
-///   2. Validate and convert the subgraphs fetched info into the internal representation.
+/// Fetch the subgraphs information from the graph network subgraph.
 ///
 /// If the fetch fails or the response is empty, an error is returned.
-///
-/// Invalid info is filtered out before converting into the internal representation.
-pub async fn fetch_and_preprocess_subgraph_info(
+pub async fn fetch_subgraph_info(
     client: &mut SubgraphClient,
     timeout: Duration,
-) -> anyhow::Result<PreprocessedNetworkInfo> {
-    // Fetch the subgraphs information from the graph network subgraph
+) -> anyhow::Result<Vec<subgraph_client::types::Subgraph>> {
     let data = tokio::time::timeout(timeout, client.fetch()).await??;
     anyhow::ensure!(!data.is_empty(), "empty subgraph response");
+    Ok(data)
+}
 
-    // Pre-process (validate and convert) the fetched subgraphs information
+/// Performs the pre-processing steps on the subgraphs information fetched from the graph network
+/// subgraph, i.e., validation and conversion into the internal representation.
+///
+/// Invalid info is filtered out before converting into the internal representation.
+pub fn preprocess_subgraph_info(
+    data: &[subgraph_client::types::Subgraph],
+) -> PreprocessedNetworkInfo {
     let indexers = pre_processing::into_internal_indexers_raw_info(data.iter());
-    let subgraphs = pre_processing::into_internal_subgraphs_raw_info(data.into_iter());
+    let subgraphs = pre_processing::into_internal_subgraphs_raw_info(data.iter().cloned());
     let deployments = pre_processing::into_internal_deployments_raw_info(subgraphs.values());
 
     let subgraphs = subgraph_processing::process_subgraph_info(subgraphs);
     let deployments = subgraph_processing::process_deployments_info(deployments);
 
-    Ok(PreprocessedNetworkInfo {
+    PreprocessedNetworkInfo {
         subgraphs,
         deployments,
         indexers,
-    })
+    }
 }
 
 #[cfg(test)]
@@ -1,4 +1,7 @@
-use std::collections::{HashMap, HashSet};
+use std::{
+    collections::{HashMap, HashSet},
+    net::IpAddr,
+};
 
 use cost_model::CostModel;
 use custom_debug::CustomDebug;
@@ -250,6 +253,143 @@ pub(super) async fn process_info(
     FromIterator::from_iter(processed_info)
 }
 
+/// Process the fetched network topology information, using only the information cached by the
+/// resolvers (and the given indexer host addresses), without sending any requests.
+///
+/// Indexers whose information is not cached are marked as unhealthy. As the indexers' public POIs
+/// are not cached, the indexings affected by the POI blocklist are marked as blocked.
+pub(super) fn process_info_from_cache(
+    state: &InternalState,
+    indexer_hosts: &HashMap<String, Vec<IpAddr>>,
+    indexers: &HashMap<IndexerId, IndexerRawInfo>,
+) -> HashMap<IndexerId, Result<ResolvedIndexerInfo, IndexerInfoResolutionError>> {
+    indexers
+        .iter()
+        .map(|(indexer_id, indexer)| {
+            let info = process_indexer_info_from_cache(state, indexer_hosts, indexer);
+            (*indexer_id, info)
+        })
+        .collect()
+}
+
+/// Process the indexer information, using only the cached information.
+///
+/// See [`process_info_from_cache`] for more information.
+fn process_indexer_info_from_cache(
+    state: &InternalState,
+    indexer_hosts: &HashMap<String, Vec<IpAddr>>,
+    indexer: &IndexerRawInfo,
+) -> Result<ResolvedIndexerInfo, IndexerInfoResolutionError> {
+    // Check if the indexer's host is in the host blocklist
+    if !state.indexer_host_blocklist.is_empty() {
+        let addrs = indexer
+            .url
+            .host_str()
+            .and_then(|host| indexer_hosts.get(host))
+            .ok_or(IndexerInfoResolutionError::NotCached)?;
+        if addrs.iter().any(|addr| {
+            state
+                .indexer_host_blocklist
+                .iter()
+                .any(|net| net.contains(*addr))
+        }) {
+            return Err(IndexerInfoResolutionError::BlockedHost);
+        }
+    }
+
+    // Check if the indexer's cached versions are supported
+    let version_requirements = &state.indexer_version_requirements;
+    let indexer_service_version = state
+        .indexer_version_resolver
+        .cached_indexer_service_version(&indexer.url)
+        .ok_or(IndexerInfoResolutionError::NotCached)?;
+    if indexer_service_version < version_requirements.min_indexer_service_version {
+        return Err(IndexerInfoResolutionError::IndexerServiceVersionBelowMin(
+            indexer_service_version,
+            version_requirements.min_indexer_service_version.clone(),
+        ));
+    }
+    let graph_node_version = state
+        .indexer_version_resolver
+        .cached_graph_node_version(&indexer.url)
+        .unwrap_or_else(|| version_requirements.min_graph_node_version.clone());
+    if graph_node_version < version_requirements.min_graph_node_version {
+        return Err(IndexerInfoResolutionError::GraphNodeVersionBelowMin(
+            graph_node_version,
+            version_requirements.min_graph_node_version.clone(),
+        ));
+    }
+
+    let blocklist = state.indexer_blocklist.get(&*indexer.id);
+    let indexer_indexings = apply_indexer_blocklist(indexer.indexings.clone(), blocklist);
+
+    let deployments = indexer_indexings.keys().copied().collect::<Vec<_>>();
+    let mut progress_info = state
+        .indexing_progress_resolver
+        .resolve_from_cache(&indexer.url);
+    let cost_model_sources = state
+        .cost_model_resolver
+        .resolve_from_cache(&indexer.url, &deployments);
+
+    let indexings = indexer_indexings
+        .into_iter()
+        .map(|(id, res)| {
+            let info = match res {
+                Ok(info) => info,
+                Err(err) => return (id, Err(err)),
+            };
+
+            if !state.poi_blocklist.affected_pois_metadata([&id]).is_empty() {
+                return (
+                    id,
+                    Err(IndexingInfoResolutionError::Blocked(
+                        "unverified POI".into(),
+                    )),
+                );
+            }
+
+            let progress = match progress_info.remove(&id) {
+                Some(info) => IndexingProgress {
+                    latest_block: info.latest_block,
+                    min_block: info.min_block,
+                },
+                None => {
+                    return (
+                        id,
+                        Err(IndexingInfoResolutionError::IndexingProgressNotFound),
+                    );
+                }
+            };
+
+            let cost_model = cost_model_sources.get(&id).and_then(|source| {
+                match state.cost_model_compiler.compile(source.as_ref()) {
+                    Err(err) => {
+                        tracing::debug!("cost model compilation failed: {err}");
+                        None
+                    }
+                    Ok(cost_model) => Some(cost_model),
+                }
+            });
+
+            (
+                id,
+                Ok(info
+                    .with_indexing_progress(progress)
+                    .with_cost_model(cost_model)),
+            )
+        })
+        .collect();
+
+    Ok(IndexerInfo {
+        id: indexer.id,
+        url: indexer.url.clone(),
+        staked_tokens: indexer.staked_tokens,
+        indexer_service_version,
+        graph_node_version,
+        indexings,
+    })
+}
+
 /// Resolve and check if the indexer's host is in the host blocklist.
 ///
 /// - If the indexer's host is not resolvable: the indexer is BLOCKED.
@@ -323,31 +463,7 @@ async fn process_indexer_indexings(
     indexings: HashMap<DeploymentId, IndexingRawInfo>,
     blocklist: Option<&BlockedIndexer>,
 ) -> HashMap<DeploymentId, Result<ResolvedIndexingInfo, IndexingInfoResolutionError>> {
-    let mut indexer_indexings: HashMap<DeploymentId, Result<IndexingInfo<(), ()>, _>> = indexings
-        .into_iter()
-        .map(|(id, info)| (id, Ok(info.into())))
-        .collect();
-
-    match blocklist {
-        None => (),
-        Some(blocklist) if blocklist.deployments.is_empty() => {
-            for entry in indexer_indexings.values_mut() {
-                *entry = Err(IndexingInfoResolutionError::Blocked(
-                    blocklist.reason.clone(),
-                ));
-            }
-        }
-        Some(blocklist) => {
-            for deployment in &blocklist.deployments {
-                indexer_indexings.insert(
-                    *deployment,
-                    Err(IndexingInfoResolutionError::Blocked(
-                        blocklist.reason.clone(),
-                    )),
-                );
-            }
-        }
-    };
+    let indexer_indexings = apply_indexer_blocklist(indexings, blocklist);
 
     // Keep track of the healthy indexers, so we efficiently resolve the indexer's indexings thar
     // are not marked as unhealthy in a previous resolution step
@@ -445,6 +561,40 @@ async fn process_indexer_indexings(
     indexer_indexings
 }
 
+/// Mark the indexer's indexings blocked by the indexer blocklist as unhealthy.
+fn apply_indexer_blocklist(
+    indexings: HashMap<DeploymentId, IndexingRawInfo>,
+    blocklist: Option<&BlockedIndexer>,
+) -> HashMap<DeploymentId, Result<IndexingInfo<(), ()>, IndexingInfoResolutionError>> {
+    let mut indexer_indexings: HashMap<DeploymentId, Result<IndexingInfo<(), ()>, _>> = indexings
+        .into_iter()
+        .map(|(id, info)| (id, Ok(info.into())))
+        .collect();
+
+    match blocklist {
+        None => (),
+        Some(blocklist) if blocklist.deployments.is_empty() => {
+            for entry in indexer_indexings.values_mut() {
+                *entry = Err(IndexingInfoResolutionError::Blocked(
+                    blocklist.reason.clone(),
+                ));
+            }
+        }
+        Some(blocklist) => {
+            for deployment in &blocklist.deployments {
+                indexer_indexings.insert(
+                    *deployment,
+                    Err(IndexingInfoResolutionError::Blocked(
+                        blocklist.reason.clone(),
+                    )),
+                );
+            }
+        }
+    };
+
+    indexer_indexings
+}
+
 /// Resolve and check if any of the indexer's indexings should be blocked by POI.
 async fn resolve_and_check_indexer_indexings_blocked_by_poi(
     blocklist: &PoiBlocklist,
@@ -162,6 +162,9 @@ pub struct NetworkTopologySnapshot {
     pub subgraphs: HashMap<SubgraphId, Result<Subgraph, SubgraphError>>,
     /// Deployments network topology table.
     pub deployments: HashMap<DeploymentId, Result<Deployment, DeploymentError>>,
+    /// Whether the snapshot was constructed from the network cache, and has not been updated
+    /// with live information yet.
+    pub stale: bool,
 }
 
 /// Construct the [`NetworkTopologySnapshot`] from the indexers and subgraphs information.
@@ -222,6 +225,7 @@ pub fn new_from(
     NetworkTopologySnapshot {
         deployments,
         subgraphs,
+        stale: false,
     }
 }
 
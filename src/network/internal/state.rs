@@ -6,11 +6,12 @@ use thegraph_core::Address;
 use crate::{
     config::BlockedIndexer,
     network::{
-        config::VersionRequirements as IndexerVersionRequirements,
+        config::{NetworkSettings, VersionRequirements as IndexerVersionRequirements},
         indexer_host_resolver::HostResolver,
         indexer_indexing_cost_model_compiler::CostModelCompiler,
         indexer_indexing_cost_model_resolver::CostModelResolver,
-        indexer_indexing_poi_blocklist::PoiBlocklist, indexer_indexing_poi_resolver::PoiResolver,
+        indexer_indexing_poi_blocklist::PoiBlocklist,
+        indexer_indexing_poi_resolver::PoiResolver,
         indexer_indexing_progress_resolver::IndexingProgressResolver,
         indexer_version_resolver::VersionResolver,
     },
@@ -28,3 +29,15 @@ pub struct InternalState {
     pub cost_model_resolver: CostModelResolver,
     pub cost_model_compiler: CostModelCompiler,
 }
+
+impl InternalState {
+    /// Apply the given settings, replacing the indexer blocklists and version requirements.
+    pub fn apply_settings(&mut self, settings: &NetworkSettings) {
+        self.indexer_blocklist = settings.indexer_blocklist.clone();
+        self.indexer_version_requirements = IndexerVersionRequirements {
+            min_indexer_service_version: settings.min_indexer_service_version.clone(),
+            min_graph_node_version: settings.min_graph_node_version.clone(),
+        };
+        self.poi_blocklist = PoiBlocklist::new(settings.poi_blocklist.clone());
+    }
+}
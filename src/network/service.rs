@@ -3,17 +3,21 @@
 //! query processing pipeline
 
 use std::{
-    collections::{BTreeMap, HashMap, HashSet},
+    collections::{HashMap, HashSet},
+    path::PathBuf,
     time::Duration,
 };
 
 use ipnetwork::IpNetwork;
-use semver::Version;
-use thegraph_core::{Address, BlockNumber, DeploymentId, SubgraphId};
-use tokio::{sync::watch, time::MissedTickBehavior};
+use thegraph_core::{AllocationId, BlockNumber, DeploymentId, SubgraphId};
+use tokio::{
+    sync::watch,
+    time::{Interval, MissedTickBehavior},
+};
 
 use super::{
-    config::VersionRequirements,
+    cache::{self, NetworkCache},
+    config::{NetworkSettings, VersionRequirements},
     errors::{DeploymentError, SubgraphError},
     indexer_host_resolver::HostResolver,
     indexer_indexing_cost_model_compiler::CostModelCompiler,
@@ -23,13 +27,13 @@ use super::{
     indexer_indexing_progress_resolver::IndexingProgressResolver,
     indexer_version_resolver::VersionResolver,
     internal::{
-        fetch_and_preprocess_subgraph_info, fetch_update, Indexing, IndexingId, InternalState,
-        NetworkTopologySnapshot, PreprocessedNetworkInfo,
+        cached_update, fetch_subgraph_info, fetch_update, preprocess_subgraph_info, Indexing,
+        IndexingId, InternalState, NetworkTopologySnapshot, PreprocessedNetworkInfo,
     },
-    subgraph_client::Client as SubgraphClient,
+    subgraph_client::{self, Client as SubgraphClient},
     ResolutionError,
 };
-use crate::{config::BlockedIndexer, indexers::public_poi::ProofOfIndexingInfo};
+use crate::time::unix_timestamp;
 
 /// Subgraph resolution information returned by the [`NetworkService`].
 pub struct ResolvedSubgraphInfo {
@@ -159,6 +163,14 @@ impl NetworkService {
         }))
     }
 
+    /// Get the current network topology snapshot.
+    ///
+    /// Note: the returned reference holds a read lock on the snapshot. It must not be held across
+    /// await points.
+    pub fn snapshot(&self) -> watch::Ref<'_, NetworkTopologySnapshot> {
+        self.network.borrow()
+    }
+
     /// Get the latest indexed block number reported by the indexers.
     pub fn indexing_progress(&self) -> HashMap<IndexingId, BlockNumber> {
         self.network
@@ -169,30 +181,42 @@ impl NetworkService {
             .flat_map(|(id, indexing)| indexing.iter().map(|i| (*id, i.progress.latest_block)))
             .collect()
     }
+
+    /// Get the largest allocation of each indexing, which receipts are issued for.
+    pub fn largest_allocations(&self) -> HashSet<AllocationId> {
+        self.network
+            .borrow()
+            .deployments
+            .iter()
+            .flat_map(|(_, result)| result.iter().flat_map(|d| &d.indexings))
+            .flat_map(|(_, indexing)| indexing.iter().map(|i| i.largest_allocation))
+            .collect()
+    }
 }
 
+/// Spawn the network service.
+///
+/// Updates to the `settings` are applied to the network topology on the next update, which is
+/// triggered immediately.
+///
+/// If a `cache_path` is provided, the network topology is restored from the network cache (if
+/// present) and marked as stale until the first update completes. The cache is written after every
+/// update.
 pub fn spawn(
     http_client: reqwest::Client,
     subgraph_client: SubgraphClient,
-    min_indexer_service_version: Version,
-    min_graph_node_version: Version,
-    indexer_blocklist: BTreeMap<Address, BlockedIndexer>,
     indexer_host_blocklist: HashSet<IpNetwork>,
-    indexer_pois_blocklist: Vec<ProofOfIndexingInfo>,
+    mut settings: watch::Receiver<NetworkSettings>,
+    cache_path: Option<PathBuf>,
 ) -> NetworkService {
-    let internal_state = InternalState {
-        indexer_blocklist,
+    let mut internal_state = InternalState {
+        indexer_blocklist: Default::default(),
         indexer_host_resolver: HostResolver::new(Duration::from_secs(5))
             .expect("failed to create host resolver"),
         indexer_host_blocklist,
-        indexer_version_requirements: VersionRequirements {
-            min_indexer_service_version,
-            min_graph_node_version,
-        },
+        indexer_version_requirements: VersionRequirements::default(),
         indexer_version_resolver: VersionResolver::new(http_client.clone(), Duration::from_secs(5)),
-        poi_blocklist: PoiBlocklist::new(
-            indexer_pois_blocklist.into_iter().map(Into::into).collect(),
-        ),
+        poi_blocklist: PoiBlocklist::default(),
         poi_resolver: PoiResolver::new(
             http_client.clone(),
             Duration::from_secs(5),
@@ -205,8 +229,16 @@ pub fn spawn(
         cost_model_resolver: CostModelResolver::new(http_client.clone(), Duration::from_secs(5)),
         cost_model_compiler: CostModelCompiler::new(Duration::from_secs(12 * 60 * 60)),
     };
+    internal_state.apply_settings(&settings.borrow_and_update());
+
     let update_interval = Duration::from_secs(60);
-    let network = spawn_updater_task(subgraph_client, internal_state, update_interval);
+    let network = spawn_updater_task(
+        subgraph_client,
+        internal_state,
+        settings,
+        cache_path,
+        update_interval,
+    );
 
     NetworkService { network }
 }
@@ -215,23 +247,56 @@ pub fn spawn(
 /// subgraph at regular intervals
 fn spawn_updater_task(
     mut subgraph_client: SubgraphClient,
-    state: InternalState,
+    mut state: InternalState,
+    mut settings: watch::Receiver<NetworkSettings>,
+    cache_path: Option<PathBuf>,
     update_interval: Duration,
 ) -> watch::Receiver<NetworkTopologySnapshot> {
-    let (tx, rx) = watch::channel(Default::default());
+    let mut network_data: Option<Vec<subgraph_client::types::Subgraph>> = None;
+    let mut network_info: Option<PreprocessedNetworkInfo> = None;
 
-    tokio::spawn(async move {
-        let mut network_info: Option<PreprocessedNetworkInfo> = None;
+    // Restore the network topology from the network cache, if available
+    let mut initial_snapshot = NetworkTopologySnapshot::default();
+    match cache_path
+        .as_deref()
+        .filter(|path| path.exists())
+        .map(cache::load)
+    {
+        None => (),
+        Some(Err(network_cache_load_err)) => {
+            tracing::warn!(network_cache_load_err = format!("{network_cache_load_err:#}"));
+        }
+        Some(Ok(cache)) => {
+            cache.restore(&state);
+            let info = preprocess_subgraph_info(&cache.network_subgraph);
+            initial_snapshot = cached_update(&info, &state, &cache.indexer_hosts);
+            tracing::warn!(
+                age_s = unix_timestamp().saturating_sub(cache.timestamp) / 1_000,
+                subgraphs = initial_snapshot.subgraphs.len(),
+                deployments = initial_snapshot.deployments.len(),
+                "network topology restored from cache (stale)",
+            );
+            network_data = Some(cache.network_subgraph);
+            network_info = Some(info);
+        }
+    };
+
+    let (tx, rx) = watch::channel(initial_snapshot);
 
+    tokio::spawn(async move {
         let mut timer = tokio::time::interval(update_interval);
         timer.set_missed_tick_behavior(MissedTickBehavior::Skip);
         loop {
-            timer.tick().await;
+            if let Some(settings) = wait_for_update(&mut timer, &mut settings).await {
+                state.apply_settings(&settings);
+                tracing::info!("network settings updated");
+            }
 
-            match fetch_and_preprocess_subgraph_info(&mut subgraph_client, Duration::from_secs(30))
-                .await
-            {
-                Ok(info) => network_info = Some(info),
+            match fetch_subgraph_info(&mut subgraph_client, Duration::from_secs(30)).await {
+                Ok(data) => {
+                    network_info = Some(preprocess_subgraph_info(&data));
+                    network_data = Some(data);
+                }
                 Err(network_subgraph_update_err) => tracing::error!(%network_subgraph_update_err),
             };
             let network_info = match &network_info {
@@ -251,8 +316,66 @@ fn spawn_updater_task(
             );
 
             let _ = tx.send(snapshot);
+
+            if let (Some(path), Some(data)) = (&cache_path, &network_data) {
+                let path = path.clone();
+                let cache = NetworkCache::collect(data.clone(), &state);
+                tokio::task::spawn_blocking(move || {
+                    if let Err(network_cache_store_err) = cache::store(&path, &cache) {
+                        tracing::error!(
+                            network_cache_store_err = format!("{network_cache_store_err:#}")
+                        );
+                    }
+                });
+            }
         }
     });
 
     rx
 }
+
+/// Wait for the next network topology update, triggered either by the timer or by a change of the
+/// settings. Returns the new settings, if they have changed.
+async fn wait_for_update(
+    timer: &mut Interval,
+    settings: &mut watch::Receiver<NetworkSettings>,
+) -> Option<NetworkSettings> {
+    tokio::select! {
+        _ = timer.tick() => None,
+        Ok(()) = settings.changed() => Some(settings.borrow_and_update().clone()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use semver::Version;
+
+    use super::*;
+
+    fn settings(min_indexer_service_version: Version) -> NetworkSettings {
+        NetworkSettings {
+            min_indexer_service_version,
+            min_graph_node_version: Version::new(0, 0, 0),
+            indexer_blocklist: Default::default(),
+            poi_blocklist: Default::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn settings_changes_trigger_an_update() {
+        //* Given
+        let (tx, mut rx) = watch::channel(settings(Version::new(0, 0, 0)));
+        rx.borrow_and_update();
+        let mut timer = tokio::time::interval(Duration::from_secs(3600));
+        // The first tick completes immediately
+        timer.tick().await;
+
+        //* When
+        tx.send(settings(Version::new(1, 0, 0))).unwrap();
+        let update = wait_for_update(&mut timer, &mut rx).await;
+
+        //* Then
+        assert_eq!(update, Some(settings(Version::new(1, 0, 0))));
+        assert!(!rx.has_changed().unwrap());
+    }
+}
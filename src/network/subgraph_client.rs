@@ -24,7 +24,8 @@ use crate::{
 /// The Graph network subgraph types.
 ///
 /// <div class="warning">
-/// These types are used to deserialize the response from the Graph network subgraph.
+/// These types are used to deserialize the response from the Graph network subgraph, and to
+/// persist it in the network cache.
 /// These types are not meant to be used directly by the gateway logic.
 ///
 /// Please, DO NOT mix or merge them.
@@ -32,18 +33,18 @@ use crate::{
 ///
 /// See: https://github.com/graphprotocol/graph-network-subgraph/blob/master/schema.graphql
 pub mod types {
-    use serde::Deserialize;
+    use serde::{Deserialize, Serialize};
     use serde_with::serde_as;
     use thegraph_core::{AllocationId, BlockNumber, DeploymentId, IndexerId, SubgraphId};
 
-    #[derive(Debug, Clone, Deserialize)]
+    #[derive(Debug, Clone, Serialize, Deserialize)]
     #[serde(rename_all = "camelCase")]
     pub struct Subgraph {
         pub id: SubgraphId,
         pub versions: Vec<SubgraphVersion>,
     }
 
-    #[derive(Debug, Clone, Deserialize)]
+    #[derive(Debug, Clone, Serialize, Deserialize)]
     #[serde(rename_all = "camelCase")]
     pub struct SubgraphVersion {
         pub version: u32,
@@ -51,7 +52,7 @@ pub mod types {
     }
 
     #[serde_as]
-    #[derive(Debug, Clone, Deserialize)]
+    #[derive(Debug, Clone, Serialize, Deserialize)]
     #[serde(rename_all = "camelCase")]
     pub struct Manifest {
         pub network: Option<String>,
@@ -59,7 +60,7 @@ pub mod types {
         pub start_block: BlockNumber,
     }
 
-    #[derive(Debug, Clone, Deserialize)]
+    #[derive(Debug, Clone, Serialize, Deserialize)]
     #[serde(rename_all = "camelCase")]
     pub struct SubgraphDeployment {
         #[serde(rename = "ipfsHash")]
@@ -70,7 +71,7 @@ pub mod types {
     }
 
     #[serde_as]
-    #[derive(Debug, Clone, Deserialize)]
+    #[derive(Debug, Clone, Serialize, Deserialize)]
     #[serde(rename_all = "camelCase")]
     pub struct Allocation {
         pub id: AllocationId,
@@ -80,7 +81,7 @@ pub mod types {
     }
 
     #[serde_as]
-    #[derive(Debug, Clone, Deserialize)]
+    #[derive(Debug, Clone, Serialize, Deserialize)]
     #[serde(rename_all = "camelCase")]
     pub struct Indexer {
         pub id: IndexerId,
@@ -91,7 +92,7 @@ pub mod types {
 }
 
 #[serde_as]
-#[derive(Clone, CustomDebug, Deserialize)]
+#[derive(Clone, CustomDebug, PartialEq, Deserialize)]
 pub struct TrustedIndexer {
     /// network subgraph endpoint
     #[debug(with = std::fmt::Display::fmt)]
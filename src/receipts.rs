@@ -1,21 +1,40 @@
-use std::{collections::HashMap, sync::Arc, time::SystemTime};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
 
 use alloy_primitives::U256;
 use alloy_sol_types::Eip712Domain;
-use ethers::{core::k256::ecdsa::SigningKey, signers::Wallet};
+use anyhow::{anyhow, ensure};
+use ethers::{
+    core::k256::ecdsa::SigningKey,
+    signers::{Signer as _, Wallet},
+};
 use parking_lot::{Mutex, RwLock};
 use rand::RngCore;
 pub use receipts::QueryStatus as ReceiptStatus;
 use receipts::ReceiptPool;
 use secp256k1::SecretKey;
-use tap_core::{receipt::Receipt as TapReceipt, signed_message::EIP712SignedMessage};
-use thegraph_core::{Address, AllocationId};
+use tap_core::{
+    rav::ReceiptAggregateVoucher, receipt::Receipt as TapReceipt,
+    signed_message::EIP712SignedMessage,
+};
+use thegraph_core::{Address, AllocationId, IndexerId};
+use tokio::sync::watch;
+
+use self::{escrow::Escrow, ledger::ReceiptLedger};
+use crate::{network::NetworkService, time::unix_timestamp};
+
+pub mod escrow;
+pub mod ledger;
 
 /// A receipt for an indexer request.
 #[derive(Debug, Clone)]
 pub enum Receipt {
     Legacy(u128, Vec<u8>),
-    TAP(EIP712SignedMessage<TapReceipt>),
+    /// TAP receipt, with the address of its signer
+    TAP(EIP712SignedMessage<TapReceipt>, Address),
 }
 
 impl Receipt {
@@ -23,7 +42,7 @@ impl Receipt {
     pub fn grt_value(&self) -> u128 {
         match self {
             Receipt::Legacy(value, _) => *value,
-            Receipt::TAP(receipt) => receipt.message.value,
+            Receipt::TAP(receipt, _) => receipt.message.value,
         }
     }
 
@@ -31,7 +50,7 @@ impl Receipt {
     pub fn allocation(&self) -> Address {
         match self {
             Receipt::Legacy(_, receipt) => Address::from_slice(&receipt[0..20]),
-            Receipt::TAP(receipt) => receipt.message.allocation_id,
+            Receipt::TAP(receipt, _) => receipt.message.allocation_id,
         }
     }
 
@@ -40,7 +59,7 @@ impl Receipt {
     pub fn serialize(&self) -> String {
         match self {
             Receipt::Legacy(_, receipt) => hex::encode(&receipt[..(receipt.len() - 32)]),
-            Receipt::TAP(receipt) => serde_json::to_string(&receipt).unwrap(),
+            Receipt::TAP(receipt, _) => serde_json::to_string(&receipt).unwrap(),
         }
     }
 
@@ -49,14 +68,21 @@ impl Receipt {
     pub fn header_name(&self) -> &'static str {
         match self {
             Receipt::Legacy(_, _) => "Scalar-Receipt",
-            Receipt::TAP(_) => "Tap-Receipt",
+            Receipt::TAP(_, _) => "Tap-Receipt",
         }
     }
 }
 
+/// Maximum number of receipts aggregated by a single RAV request.
+const MAX_RAV_RECEIPTS: usize = 15_000;
+
+/// Maximum value of a RAV, 10M GRT (in wei).
+const MAX_RAV_VALUE: u128 = 10_000_000_000_000_000_000_000_000;
+
 /// Scalar TAP signer.
 struct TapSigner {
     signer: Wallet<SigningKey>,
+    address: Address,
     domain: Eip712Domain,
 }
 
@@ -64,9 +90,11 @@ impl TapSigner {
     /// Creates a new `TapSigner`.
     fn new(signer: SecretKey, chain_id: U256, verifying_contract: Address) -> Self {
         let signer = Wallet::from_bytes(signer.as_ref()).expect("failed to prepare receipt wallet");
+        let address = Address::from_slice(signer.address().as_bytes());
 
         Self {
             signer,
+            address,
             domain: Eip712Domain {
                 name: Some("TAP".into()),
                 version: Some("1".into()),
@@ -106,13 +134,159 @@ impl TapSigner {
 
         Ok(signed)
     }
+
+    /// Aggregates the receipts into a Receipt Aggregate Voucher (RAV), on top of the previous RAV
+    /// for the same allocation.
+    ///
+    /// The receipts must be signed by one of the `receipt_signers`, and the previous RAV by one of
+    /// the `rav_signers`, for this signer's domain, for a single allocation. The receipts must
+    /// have unique nonces, and be more recent than the previous RAV.
+    fn aggregate_receipts(
+        &self,
+        receipts: &[EIP712SignedMessage<TapReceipt>],
+        previous_rav: Option<EIP712SignedMessage<ReceiptAggregateVoucher>>,
+        receipt_signers: &[Address],
+        rav_signers: &[Address],
+    ) -> anyhow::Result<EIP712SignedMessage<ReceiptAggregateVoucher>> {
+        ensure!(!receipts.is_empty(), "no receipts");
+        ensure!(
+            receipts.len() <= MAX_RAV_RECEIPTS,
+            "too many receipts (max {MAX_RAV_RECEIPTS})"
+        );
+
+        let allocation = receipts[0].message.allocation_id;
+        let mut min_timestamp_ns = 0;
+        if let Some(previous_rav) = &previous_rav {
+            self.check_signer(previous_rav, rav_signers)
+                .map_err(|err| anyhow!("previous RAV: {err}"))?;
+            ensure!(
+                previous_rav.message.allocationId == allocation,
+                "previous RAV allocation mismatch"
+            );
+            min_timestamp_ns = previous_rav.message.timestampNs;
+        }
+
+        let mut nonces = HashSet::with_capacity(receipts.len());
+        for receipt in receipts {
+            self.check_signer(receipt, receipt_signers)?;
+            ensure!(
+                receipt.message.allocation_id == allocation,
+                "receipts for multiple allocations"
+            );
+            ensure!(
+                receipt.message.timestamp_ns > min_timestamp_ns,
+                "receipt not more recent than previous RAV"
+            );
+            ensure!(
+                nonces.insert(receipt.message.nonce),
+                "duplicate receipt nonce"
+            );
+        }
+
+        let rav = ReceiptAggregateVoucher::aggregate_receipts(allocation, receipts, previous_rav)
+            .map_err(|err| anyhow!("failed to aggregate receipts: {err}"))?;
+        ensure!(rav.valueAggregate <= MAX_RAV_VALUE, "RAV value too large");
+        EIP712SignedMessage::new(&self.domain, rav, &self.signer)
+            .map_err(|err| anyhow!("failed to sign RAV: {err:?}"))
+    }
+
+    /// Check that the message was signed by one of the accepted signers, for this signer's domain.
+    fn check_signer<M>(
+        &self,
+        message: &EIP712SignedMessage<M>,
+        accepted_signers: &[Address],
+    ) -> anyhow::Result<()>
+    where
+        M: alloy_sol_types::SolStruct,
+    {
+        let signer = message
+            .recover_signer(&self.domain)
+            .map_err(|err| anyhow!("invalid signature: {err}"))?;
+        ensure!(accepted_signers.contains(&signer), "unexpected signer");
+        Ok(())
+    }
+}
+
+/// TAP signer keys, with the time at which each becomes the active signer.
+#[derive(Clone, PartialEq)]
+pub struct SignerKeys {
+    /// Keys, with their activation time in seconds since the Unix epoch
+    pub keys: Vec<(SecretKey, u64)>,
+    /// Time that a signer remains accepted for the receipts of RAV requests, after it's superseded
+    pub grace_period: Duration,
+}
+
+/// Scalar TAP signers, rotated on schedule.
+struct TapSigners {
+    /// Signers, with their activation time, in order of activation
+    signers: Vec<(u64, TapSigner)>,
+    grace_period_secs: u64,
+}
+
+impl TapSigners {
+    fn new(keys: &SignerKeys, chain_id: U256, verifying_contract: Address) -> Self {
+        assert!(!keys.keys.is_empty(), "no TAP signer keys");
+        let mut signers: Vec<(u64, TapSigner)> = keys
+            .keys
+            .iter()
+            .map(|(key, active_from)| {
+                let signer = TapSigner::new(*key, chain_id, verifying_contract);
+                (*active_from, signer)
+            })
+            .collect();
+        signers.sort_by_key(|(active_from, _)| *active_from);
+        Self {
+            signers,
+            grace_period_secs: keys.grace_period.as_secs(),
+        }
+    }
+
+    /// Returns the index of the most recently activated signer, or the earliest signer if none
+    /// is active yet.
+    fn active_index(&self, now_secs: u64) -> usize {
+        self.signers
+            .partition_point(|(active_from, _)| *active_from <= now_secs)
+            .saturating_sub(1)
+    }
+
+    /// Returns the signer used for new receipts and RAVs.
+    fn active(&self, now_secs: u64) -> &TapSigner {
+        &self.signers[self.active_index(now_secs)].1
+    }
+
+    /// Returns the addresses of the signers accepted for the receipts of RAV requests: the active
+    /// signer, and the signers superseded within the grace period. Signers that aren't active yet
+    /// are not accepted.
+    fn accepted(&self, now_secs: u64) -> Vec<Address> {
+        let active = self.active_index(now_secs);
+        (0..=active)
+            .filter(|&index| {
+                let superseded_at = match self.signers.get(index + 1) {
+                    Some((active_from, _)) if index < active => *active_from,
+                    _ => return true,
+                };
+                now_secs < superseded_at.saturating_add(self.grace_period_secs)
+            })
+            .map(|index| self.signers[index].1.address)
+            .collect()
+    }
+
+    /// Returns the addresses of the signers accepted for the previous RAV of RAV requests: the
+    /// active signer, and all the signers it superseded. So indexers can keep extending RAVs
+    /// signed before a rotation, after the grace period.
+    fn rav_signers(&self, now_secs: u64) -> Vec<Address> {
+        self.signers[..=self.active_index(now_secs)]
+            .iter()
+            .map(|(_, signer)| signer.address)
+            .collect()
+    }
 }
 
 /// Legacy Scalar signer.
 struct LegacySigner {
     secret_key: &'static SecretKey,
-    // Note: We are holding on to receipt pools indefinitely. This is acceptable, since the memory
-    // cost is minor and the typical duration of an allocation is 28 days.
+    // Note: Receipt pools are dropped once their allocation is no longer in the network topology.
+    // See `ReceiptSigner::spawn_legacy_pool_pruner`.
     receipt_pools: RwLock<HashMap<AllocationId, Arc<Mutex<ReceiptPool>>>>,
 }
 
@@ -162,31 +336,64 @@ impl LegacySigner {
             legacy_pool.lock().release(receipt, status);
         };
     }
+
+    /// Drop the receipt pools of allocations not in the given set.
+    fn retain_allocations(&self, allocations: &HashSet<AllocationId>) {
+        self.receipt_pools
+            .write()
+            .retain(|allocation, _| allocations.contains(allocation));
+    }
 }
 
 /// ReceiptSigner is responsible for creating receipts for indexing requests.
 pub struct ReceiptSigner {
-    tap: TapSigner,
-    legacy: LegacySigner,
+    tap: RwLock<TapSigners>,
+    chain_id: U256,
+    verifier: Address,
+    legacy: Option<LegacySigner>,
+    ledger: Option<&'static ReceiptLedger>,
+    escrow: Option<&'static Escrow>,
 }
 
 impl ReceiptSigner {
-    /// Creates a new `ReceiptSigner`.
+    /// Creates a new `ReceiptSigner`. Legacy receipts are disabled without a `legacy_signer`.
     pub fn new(
-        signer: SecretKey,
+        signers: &SignerKeys,
         chain_id: U256,
         verifier: Address,
-        legacy_signer: &'static SecretKey,
+        legacy_signer: Option<&'static SecretKey>,
+        ledger: Option<&'static ReceiptLedger>,
+        escrow: Option<&'static Escrow>,
     ) -> Self {
         Self {
-            tap: TapSigner::new(signer, chain_id, verifier),
-            legacy: LegacySigner::new(legacy_signer),
+            tap: RwLock::new(TapSigners::new(signers, chain_id, verifier)),
+            chain_id,
+            verifier,
+            legacy: legacy_signer.map(LegacySigner::new),
+            ledger,
+            escrow,
         }
     }
 
     /// Creates a new Scalar TAP receipt for the given allocation and fee.
-    pub fn create_receipt(&self, allocation: AllocationId, fee: u128) -> anyhow::Result<Receipt> {
-        self.tap.create_receipt(allocation, fee).map(Receipt::TAP)
+    pub fn create_receipt(
+        &self,
+        indexer: IndexerId,
+        allocation: AllocationId,
+        fee: u128,
+    ) -> anyhow::Result<Receipt> {
+        let (receipt, signer) = {
+            let tap = self.tap.read();
+            let signer = tap.active(unix_timestamp() / 1_000);
+            (signer.create_receipt(allocation, fee)?, signer.address)
+        };
+        if let Some(ledger) = self.ledger {
+            ledger.record(indexer, allocation, fee, true);
+        }
+        if let Some(escrow) = self.escrow {
+            escrow.on_receipt(indexer, allocation, fee);
+        }
+        Ok(Receipt::TAP(receipt, signer))
     }
 
     /// Creates a new Scalar legacy receipt for the given allocation and fee.
@@ -195,22 +402,106 @@ impl ReceiptSigner {
         allocation: AllocationId,
         fee: u128,
     ) -> anyhow::Result<Receipt> {
-        self.legacy
-            .create_receipt(allocation, fee)
-            .map(|(fee, receipt)| Receipt::Legacy(fee, receipt))
+        let legacy = self
+            .legacy
+            .as_ref()
+            .ok_or_else(|| anyhow!("legacy receipts disabled"))?;
+        let (fee, receipt) = legacy.create_receipt(allocation, fee)?;
+        Ok(Receipt::Legacy(fee, receipt))
     }
 
-    /// Record the receipt status and release it from the pool.
+    /// Aggregates the TAP receipts into a Receipt Aggregate Voucher (RAV), on top of the previous
+    /// RAV for the same allocation. The RAV is signed by the active signer. The receipts may be
+    /// signed by any of the accepted signers, and the previous RAV by any of the configured
+    /// signers that were active.
+    pub fn aggregate_receipts(
+        &self,
+        receipts: &[EIP712SignedMessage<TapReceipt>],
+        previous_rav: Option<EIP712SignedMessage<ReceiptAggregateVoucher>>,
+    ) -> anyhow::Result<EIP712SignedMessage<ReceiptAggregateVoucher>> {
+        let rav = {
+            let tap = self.tap.read();
+            let now_secs = unix_timestamp() / 1_000;
+            tap.active(now_secs).aggregate_receipts(
+                receipts,
+                previous_rav,
+                &tap.accepted(now_secs),
+                &tap.rav_signers(now_secs),
+            )?
+        };
+        let allocation = AllocationId::from(rav.message.allocationId);
+        if let Some(ledger) = self.ledger {
+            ledger.record_rav(
+                allocation,
+                rav.message.timestampNs,
+                rav.message.valueAggregate,
+            );
+        }
+        if let Some(escrow) = self.escrow {
+            escrow.on_rav(
+                &allocation,
+                rav.message.timestampNs,
+                rav.message.valueAggregate,
+            );
+        }
+        Ok(rav)
+    }
+
+    /// Record the receipt status and release it from the pool. Legacy receipts are added to the
+    /// ledger here, unless they are rolled back after a failed request.
     pub fn record_receipt(
         &self,
+        indexer: IndexerId,
         allocation: &AllocationId,
         receipt: &Receipt,
         status: ReceiptStatus,
     ) {
-        if let Receipt::Legacy(_, receipt) = receipt {
-            self.legacy.record_receipt(allocation, receipt, status);
+        if let (Receipt::Legacy(fee, receipt), Some(legacy)) = (receipt, &self.legacy) {
+            let rolled_back = matches!(status, ReceiptStatus::Failure);
+            if let Some(ledger) = self.ledger.filter(|_| !rolled_back) {
+                ledger.record(indexer, *allocation, *fee, false);
+            }
+            legacy.record_receipt(allocation, receipt, status);
         }
     }
+
+    /// Replace the TAP signer keys, keeping the signer domain.
+    pub fn update_signers(&self, signers: &SignerKeys) {
+        *self.tap.write() = TapSigners::new(signers, self.chain_id, self.verifier);
+    }
+
+    /// Spawn a task applying updates to the TAP signer keys, e.g. on config reload.
+    pub fn spawn_signer_updates(&'static self, mut signers: watch::Receiver<SignerKeys>) {
+        tokio::spawn(async move {
+            while signers.changed().await.is_ok() {
+                self.update_signers(&signers.borrow());
+                tracing::info!("TAP signer keys updated");
+            }
+        });
+    }
+
+    /// Returns true if legacy (Scalar) receipts may be issued, to indexers without TAP support.
+    pub fn legacy_enabled(&self) -> bool {
+        self.legacy.is_some()
+    }
+
+    /// Spawn a task dropping the legacy receipt pools of allocations that are no longer the largest
+    /// allocation of any indexing in the network topology, on each network update. Receipts are
+    /// only issued for the largest allocation of an indexing.
+    pub fn spawn_legacy_pool_pruner(&'static self, mut network: NetworkService) {
+        let legacy = match &self.legacy {
+            Some(legacy) => legacy,
+            None => return,
+        };
+        tokio::spawn(async move {
+            loop {
+                network.changed().await;
+                let allocations = network.largest_allocations();
+                legacy.retain_allocations(&allocations);
+                tracing::debug!(legacy_receipt_pools = legacy.receipt_pools.read().len());
+            }
+        });
+    }
 }
 
 #[cfg(test)]
@@ -219,6 +510,13 @@ mod tests {
 
     use super::*;
 
+    fn signer_keys(key: SecretKey) -> SignerKeys {
+        SignerKeys {
+            keys: vec![(key, 0)],
+            grace_period: Duration::ZERO,
+        }
+    }
+
     mod legacy {
         use thegraph_core::allocation_id;
 
@@ -272,6 +570,27 @@ mod tests {
             assert_eq!(receipt.0, fee);
             assert!(!receipt.1.is_empty());
         }
+
+        #[test]
+        fn receipt_pools_are_pruned() {
+            //* Given
+            let legacy_secret_key = Box::leak(Box::new(
+                SecretKey::from_slice(&[0xcd; 32]).expect("invalid secret key"),
+            ));
+            let signer = LegacySigner::new(legacy_secret_key);
+            let allocation = allocation_id!("89b23fea4e46d40e8a4c6cca723e2a03fdd4bec2");
+            let closed_allocation = allocation_id!("177b557b12f22bb17a9d73dcc994d978dd6f5f89");
+            signer.create_receipt(allocation, 1000).unwrap();
+            signer.create_receipt(closed_allocation, 1000).unwrap();
+
+            //* When
+            signer.retain_allocations(&HashSet::from([allocation]));
+
+            //* Then
+            let pools = signer.receipt_pools.read();
+            assert!(pools.contains_key(&allocation));
+            assert!(!pools.contains_key(&closed_allocation));
+        }
     }
 
     mod tap {
@@ -300,6 +619,143 @@ mod tests {
 
             assert_eq!(receipt.message.value, fee);
         }
+
+        #[test]
+        fn aggregate_receipts() {
+            //* Given
+            let signer = TapSigner::new(
+                SecretKey::from_slice(&[0xcd; 32]).expect("invalid secret key"),
+                1.try_into().expect("invalid chain id"),
+                address!("177b557b12f22bb17a9d73dcc994d978dd6f5f89"),
+            );
+            let other_signer = TapSigner::new(
+                SecretKey::from_slice(&[0xab; 32]).expect("invalid secret key"),
+                1.try_into().expect("invalid chain id"),
+                address!("177b557b12f22bb17a9d73dcc994d978dd6f5f89"),
+            );
+            let allocation = allocation_id!("89b23fea4e46d40e8a4c6cca723e2a03fdd4bec2");
+            let other_allocation = allocation_id!("f7a5dce1a3d6c0ecc6bd7ec1ad3c2a7e8e9c6c5b");
+            let receipts: Vec<_> = (0..3)
+                .map(|_| signer.create_receipt(allocation, 1000).unwrap())
+                .collect();
+
+            let accepted = [signer.address];
+
+            //* When
+            let rav = signer.aggregate_receipts(&receipts, None, &accepted, &accepted);
+
+            //* Then
+            let rav = rav.expect("failed to aggregate receipts");
+            assert_eq!(rav.message.valueAggregate, 3000);
+            let newer_receipts = vec![signer.create_receipt(allocation, 1000).unwrap()];
+            let next_rav = signer
+                .aggregate_receipts(&newer_receipts, Some(rav.clone()), &accepted, &accepted)
+                .expect("failed to aggregate receipts");
+            assert_eq!(next_rav.message.valueAggregate, 4000);
+
+            // Receipts already aggregated by the previous RAV are rejected
+            assert!(signer
+                .aggregate_receipts(&receipts, Some(rav.clone()), &accepted, &accepted)
+                .is_err());
+            // Duplicate receipts are rejected
+            let duplicates = vec![receipts[0].clone(), receipts[0].clone()];
+            assert!(signer
+                .aggregate_receipts(&duplicates, None, &accepted, &accepted)
+                .is_err());
+            // Receipts for multiple allocations are rejected
+            let mixed = vec![
+                receipts[0].clone(),
+                signer.create_receipt(other_allocation, 1000).unwrap(),
+            ];
+            assert!(signer
+                .aggregate_receipts(&mixed, None, &accepted, &accepted)
+                .is_err());
+            // Receipts from other signers are rejected
+            let foreign = vec![other_signer.create_receipt(allocation, 1000).unwrap()];
+            assert!(signer
+                .aggregate_receipts(&foreign, None, &accepted, &accepted)
+                .is_err());
+        }
+
+        #[test]
+        fn rotate_signers() {
+            //* Given
+            let keys: Vec<SecretKey> = [0xab, 0xcd, 0xef]
+                .into_iter()
+                .map(|b| SecretKey::from_slice(&[b; 32]).expect("invalid secret key"))
+                .collect();
+            let signers = TapSigners::new(
+                &SignerKeys {
+                    keys: vec![(keys[2], 3000), (keys[0], 0), (keys[1], 1000)],
+                    grace_period: Duration::from_secs(500),
+                },
+                1.try_into().expect("invalid chain id"),
+                address!("177b557b12f22bb17a9d73dcc994d978dd6f5f89"),
+            );
+            let addresses: Vec<Address> = signers.signers.iter().map(|(_, s)| s.address).collect();
+
+            //* Then
+            assert_eq!(signers.active(500).address, addresses[0]);
+            assert_eq!(signers.accepted(500), vec![addresses[0]]);
+            // The superseded signer is accepted during the grace period
+            assert_eq!(signers.active(1200).address, addresses[1]);
+            assert_eq!(signers.accepted(1200), vec![addresses[0], addresses[1]]);
+            assert_eq!(signers.accepted(1500), vec![addresses[1]]);
+            assert_eq!(signers.active(3100).address, addresses[2]);
+            assert_eq!(signers.accepted(3100), vec![addresses[1], addresses[2]]);
+            // Previous RAVs are accepted from all the signers that were active
+            assert_eq!(signers.rav_signers(500), vec![addresses[0]]);
+            assert_eq!(signers.rav_signers(3100), addresses);
+        }
+
+        #[test]
+        fn previous_rav_from_expired_signer() {
+            //* Given
+            let keys: Vec<SecretKey> = [0xab, 0xcd]
+                .into_iter()
+                .map(|b| SecretKey::from_slice(&[b; 32]).expect("invalid secret key"))
+                .collect();
+            let signers = TapSigners::new(
+                &SignerKeys {
+                    keys: vec![(keys[0], 0), (keys[1], 1000)],
+                    grace_period: Duration::from_secs(500),
+                },
+                1.try_into().expect("invalid chain id"),
+                address!("177b557b12f22bb17a9d73dcc994d978dd6f5f89"),
+            );
+            let (old_signer, new_signer) = (&signers.signers[0].1, &signers.signers[1].1);
+            let allocation = allocation_id!("89b23fea4e46d40e8a4c6cca723e2a03fdd4bec2");
+            let old_receipts = vec![old_signer.create_receipt(allocation, 1000).unwrap()];
+            let previous_rav = old_signer
+                .aggregate_receipts(&old_receipts, None, &[old_signer.address], &[])
+                .expect("failed to aggregate receipts");
+            let receipts = vec![new_signer.create_receipt(allocation, 1000).unwrap()];
+            // The old signer's grace period has ended
+            let now_secs = 1600;
+
+            //* When
+            let rav = signers.active(now_secs).aggregate_receipts(
+                &receipts,
+                Some(previous_rav.clone()),
+                &signers.accepted(now_secs),
+                &signers.rav_signers(now_secs),
+            );
+
+            //* Then
+            let rav = rav.expect("failed to aggregate receipts");
+            assert_eq!(rav.message.valueAggregate, 2000);
+            // New receipts from the old signer are rejected
+            let old_receipts = vec![old_signer.create_receipt(allocation, 1000).unwrap()];
+            assert!(signers
+                .active(now_secs)
+                .aggregate_receipts(
+                    &old_receipts,
+                    Some(previous_rav),
+                    &signers.accepted(now_secs),
+                    &signers.rav_signers(now_secs),
+                )
+                .is_err());
+        }
     }
 
     #[test]
@@ -311,10 +767,12 @@ mod tests {
         ));
 
         let signer = ReceiptSigner::new(
-            tap_secret_key,
+            &signer_keys(tap_secret_key),
             1.try_into().expect("invalid chain id"),
             allocation_id!("177b557b12f22bb17a9d73dcc994d978dd6f5f89").into_inner(),
-            legacy_secret_key,
+            Some(legacy_secret_key),
+            None,
+            None,
         );
 
         let largest_allocation = allocation_id!("89b23fea4e46d40e8a4c6cca723e2a03fdd4bec2");
@@ -328,6 +786,25 @@ mod tests {
         assert!(matches!(receipt, Receipt::Legacy(_, _)));
     }
 
+    #[test]
+    fn legacy_receipts_disabled() {
+        let tap_secret_key = SecretKey::from_slice(&[0xcd; 32]).expect("invalid secret key");
+        let signer = ReceiptSigner::new(
+            &signer_keys(tap_secret_key),
+            1.try_into().expect("invalid chain id"),
+            address!("177b557b12f22bb17a9d73dcc994d978dd6f5f89"),
+            None,
+            None,
+            None,
+        );
+        let allocation = allocation_id!("89b23fea4e46d40e8a4c6cca723e2a03fdd4bec2");
+        let indexer = IndexerId::from(Address::ZERO);
+
+        assert!(!signer.legacy_enabled());
+        assert!(signer.create_legacy_receipt(allocation, 1000).is_err());
+        assert!(signer.create_receipt(indexer, allocation, 1000).is_ok());
+    }
+
     #[test]
     fn create_tap_receipt() {
         //* Given
@@ -337,20 +814,22 @@ mod tests {
         ));
 
         let signer = ReceiptSigner::new(
-            tap_secret_key,
+            &signer_keys(tap_secret_key),
             1.try_into().expect("invalid chain id"),
             address!("177b557b12f22bb17a9d73dcc994d978dd6f5f89"),
-            legacy_secret_key,
+            Some(legacy_secret_key),
+            None,
+            None,
         );
 
         let largest_allocation = allocation_id!("89b23fea4e46d40e8a4c6cca723e2a03fdd4bec2");
         let fee = 1000;
 
         //* When
-        let res = signer.create_receipt(largest_allocation, fee);
+        let res = signer.create_receipt(IndexerId::from(Address::ZERO), largest_allocation, fee);
 
         //* Then
         let receipt = res.expect("failed to create tap receipt");
-        assert!(matches!(receipt, Receipt::TAP(_)));
+        assert!(matches!(receipt, Receipt::TAP(_, _)));
     }
 }
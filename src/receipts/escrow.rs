@@ -0,0 +1,374 @@
+//! Escrow-aware spending limits.
+//!
+//! TAP receipts are only redeemable up to the gateway's escrow balance for the indexer. The value
+//! of the TAP receipts issued to each indexer is tracked until it's aggregated into a RAV by the
+//! gateway's `/rav-request` endpoint, so that indexers are no longer selected once their
+//! outstanding receipt value would exceed their escrow balance.
+//!
+//! The outstanding value is kept in memory. On startup, it's restored from the receipt ledger if
+//! configured, as the value of the TAP receipts issued for each allocation beyond its latest RAV.
+//! Otherwise it starts from zero. Until the escrow balances are known, e.g. before the first
+//! successful poll of the escrow subgraph, indexers are unavailable unless unknown balances are
+//! explicitly allowed.
+
+use std::{collections::HashMap, time::Duration};
+
+use anyhow::{anyhow, bail, Context as _};
+use parking_lot::{Mutex, RwLock};
+use serde::Deserialize;
+use serde_json::json;
+use serde_with::{serde_as, DisplayFromStr};
+use thegraph_core::{Address, AllocationId, IndexerId};
+use thegraph_graphql_http::http::response::Error as GqlError;
+use tokio::time::{interval, MissedTickBehavior};
+
+use crate::{
+    indexer_client::{IndexerAuth, IndexerClient},
+    metrics::METRICS,
+    network::subgraph_client::TrustedIndexer,
+    receipts::ledger::AllocationTotals,
+};
+
+#[derive(Default)]
+pub struct Escrow {
+    /// Escrow balance per indexer, in GRT wei. `None` until the balances are known.
+    balances: RwLock<Option<HashMap<IndexerId, u128>>>,
+    /// Don't limit indexers while the balances are unknown
+    allow_unknown_balances: bool,
+    outstanding: Mutex<Outstanding>,
+}
+
+#[derive(Default)]
+struct Outstanding {
+    allocations: HashMap<AllocationId, (IndexerId, u128)>,
+    indexers: HashMap<IndexerId, u128>,
+    /// Timestamp (in ns) and aggregate value of the latest RAV for each allocation
+    ravs: HashMap<AllocationId, (u64, u128)>,
+}
+
+impl Escrow {
+    /// Creates an escrow tracker with fixed balances.
+    pub fn new(balances: HashMap<IndexerId, u128>) -> Self {
+        let escrow = Self::default();
+        escrow.set_balances(balances);
+        escrow
+    }
+
+    /// Creates an escrow tracker without known balances, e.g. until they are polled from the
+    /// escrow subgraph.
+    pub fn unknown(allow_unknown_balances: bool) -> Self {
+        Self {
+            allow_unknown_balances,
+            ..Default::default()
+        }
+    }
+
+    /// Restore the outstanding receipt value from the receipt ledger totals. For each allocation,
+    /// this is the value of the TAP receipts beyond its latest RAV.
+    pub fn restore(&self, totals: &HashMap<AllocationId, AllocationTotals>) {
+        let mut outstanding = self.outstanding.lock();
+        for (allocation, totals) in totals {
+            let value = totals.tap_value.saturating_sub(totals.rav_value);
+            outstanding
+                .allocations
+                .insert(*allocation, (totals.indexer, value));
+            *outstanding.indexers.entry(totals.indexer).or_default() += value;
+            if totals.rav_timestamp_ns > 0 {
+                outstanding
+                    .ravs
+                    .insert(*allocation, (totals.rav_timestamp_ns, totals.rav_value));
+            }
+        }
+        for (indexer, value) in &outstanding.indexers {
+            set_outstanding_metric(indexer, *value);
+        }
+    }
+
+    /// Returns true if the indexer's escrow balance covers its outstanding receipt value, and the
+    /// additional fee. Once the balances are known, indexers without escrow balance are never
+    /// available. While the balances are unknown, indexers are only available if unknown balances
+    /// are allowed.
+    pub fn is_available(&self, indexer: &IndexerId, fee: u128) -> bool {
+        let balance = match self.balances.read().as_ref() {
+            Some(balances) => balances.get(indexer).copied().unwrap_or(0),
+            None => {
+                METRICS.escrow_unknown.inc();
+                return self.allow_unknown_balances;
+            }
+        };
+        let outstanding = self
+            .outstanding
+            .lock()
+            .indexers
+            .get(indexer)
+            .copied()
+            .unwrap_or(0);
+        outstanding.saturating_add(fee) <= balance
+    }
+
+    /// Record a TAP receipt issued to the indexer.
+    pub fn on_receipt(&self, indexer: IndexerId, allocation: AllocationId, value: u128) {
+        let mut outstanding = self.outstanding.lock();
+        let (_, allocation_value) = outstanding
+            .allocations
+            .entry(allocation)
+            .or_insert((indexer, 0));
+        *allocation_value += value;
+        let indexer_value = outstanding.indexers.entry(indexer).or_default();
+        *indexer_value += value;
+        set_outstanding_metric(&indexer, *indexer_value);
+    }
+
+    /// Record a RAV for the allocation, with its timestamp (in ns) and aggregate value.
+    ///
+    /// The outstanding value is only reduced by the value a RAV adds to the latest RAV for the
+    /// allocation. RAVs that don't move past the latest RAV, e.g. by aggregating the same receipts
+    /// again, don't reduce it.
+    pub fn on_rav(&self, allocation: &AllocationId, timestamp_ns: u64, value_aggregate: u128) {
+        let mut outstanding = self.outstanding.lock();
+        let (last_timestamp_ns, last_value) = outstanding
+            .ravs
+            .get(allocation)
+            .copied()
+            .unwrap_or_default();
+        if (timestamp_ns <= last_timestamp_ns) || (value_aggregate <= last_value) {
+            return;
+        }
+        outstanding
+            .ravs
+            .insert(*allocation, (timestamp_ns, value_aggregate));
+        let value = value_aggregate - last_value;
+        let (indexer, aggregated) = match outstanding.allocations.get_mut(allocation) {
+            Some((indexer, allocation_value)) => {
+                // Receipts issued before a restart are only tracked if restored from the ledger.
+                let aggregated = value.min(*allocation_value);
+                *allocation_value -= aggregated;
+                (*indexer, aggregated)
+            }
+            None => return,
+        };
+        if let Some(indexer_value) = outstanding.indexers.get_mut(&indexer) {
+            *indexer_value = indexer_value.saturating_sub(aggregated);
+            set_outstanding_metric(&indexer, *indexer_value);
+        }
+    }
+
+    fn set_balances(&self, balances: HashMap<IndexerId, u128>) {
+        for (indexer, balance) in &balances {
+            METRICS
+                .escrow_balance
+                .with_label_values(&[&indexer.to_string()])
+                .set(*balance as f64 * 1e-18);
+        }
+        *self.balances.write() = Some(balances);
+    }
+
+    /// Spawn a task polling the escrow balances of the sender from the escrow subgraph, served by
+    /// the given trusted indexers.
+    pub fn spawn_subgraph_poller(
+        &'static self,
+        client: IndexerClient,
+        indexers: Vec<TrustedIndexer>,
+        sender: Address,
+        poll_interval: Duration,
+    ) {
+        tokio::spawn(async move {
+            let mut interval = interval(poll_interval);
+            interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            loop {
+                interval.tick().await;
+                match fetch_balances(&client, &indexers, sender).await {
+                    Ok(balances) => {
+                        tracing::debug!(escrow_accounts = balances.len());
+                        self.set_balances(balances);
+                    }
+                    Err(escrow_subgraph_err) => {
+                        tracing::error!(escrow_subgraph_err = format!("{escrow_subgraph_err:#}"));
+                    }
+                };
+            }
+        });
+    }
+}
+
+fn set_outstanding_metric(indexer: &IndexerId, value: u128) {
+    METRICS
+        .escrow_outstanding
+        .with_label_values(&[&indexer.to_string()])
+        .set(value as f64 * 1e-18);
+}
+
+async fn fetch_balances(
+    client: &IndexerClient,
+    indexers: &[TrustedIndexer],
+    sender: Address,
+) -> anyhow::Result<HashMap<IndexerId, u128>> {
+    for indexer in indexers {
+        match fetch_balances_from_indexer(client, indexer, sender).await {
+            Ok(balances) => return Ok(balances),
+            Err(escrow_subgraph_query_err) => {
+                tracing::warn!(
+                    indexer = %indexer.url,
+                    escrow_subgraph_query_err = format!("{escrow_subgraph_query_err:#}"),
+                );
+            }
+        };
+    }
+    bail!("escrow subgraph indexers exhausted");
+}
+
+async fn fetch_balances_from_indexer(
+    client: &IndexerClient,
+    indexer: &TrustedIndexer,
+    sender: Address,
+) -> anyhow::Result<HashMap<IndexerId, u128>> {
+    let query = r#"
+        query ($sender: String!, $first: Int!, $last: String!) {
+            escrowAccounts(
+                orderBy: id, orderDirection: asc
+                first: $first
+                where: { sender: $sender, id_gt: $last }
+            ) {
+                id
+                balance
+                totalAmountThawing
+                receiver { id }
+            }
+        }"#;
+
+    #[derive(Deserialize)]
+    struct QueryResponse {
+        data: Option<QueryData>,
+        #[serde(default)]
+        errors: Vec<GqlError>,
+    }
+    #[derive(Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    struct QueryData {
+        escrow_accounts: Vec<EscrowAccount>,
+    }
+    #[serde_as]
+    #[derive(Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    struct EscrowAccount {
+        id: String,
+        #[serde_as(as = "DisplayFromStr")]
+        balance: u128,
+        #[serde_as(as = "DisplayFromStr")]
+        total_amount_thawing: u128,
+        receiver: Receiver,
+    }
+    #[derive(Deserialize)]
+    struct Receiver {
+        id: IndexerId,
+    }
+
+    const PAGE_SIZE: usize = 1000;
+    let mut balances = HashMap::new();
+    let mut last_id = String::new();
+    loop {
+        let page_query = json!({
+            "query": query,
+            "variables": {
+                "sender": sender.to_string().to_lowercase(),
+                "first": PAGE_SIZE,
+                "last": last_id,
+            },
+        });
+        let response = client
+            .query_indexer(
+                indexer.url.clone(),
+                IndexerAuth::Free(&indexer.auth),
+                &page_query.to_string(),
+            )
+            .await?;
+        let response: QueryResponse =
+            serde_json::from_str(&response.client_response).context("parse body")?;
+        if !response.errors.is_empty() {
+            bail!("{:?}", response.errors);
+        }
+        let accounts = response
+            .data
+            .ok_or_else(|| anyhow!("response missing data"))?
+            .escrow_accounts;
+        let page_len = accounts.len();
+        for account in accounts {
+            // Thawing funds are about to be withdrawn by the sender, so they don't cover new
+            // receipts.
+            let balance = account.balance.saturating_sub(account.total_amount_thawing);
+            balances.insert(account.receiver.id, balance);
+            last_id = account.id;
+        }
+        if page_len < PAGE_SIZE {
+            break;
+        }
+    }
+    Ok(balances)
+}
+
+#[cfg(test)]
+mod tests {
+    use thegraph_core::allocation_id;
+
+    use super::*;
+
+    #[test]
+    fn outstanding_value_is_limited_by_escrow() {
+        //* Given
+        let indexer = IndexerId::from(Address::with_last_byte(1));
+        let allocation = allocation_id!("89b23fea4e46d40e8a4c6cca723e2a03fdd4bec2");
+        let escrow = Escrow::new(HashMap::from([(indexer, 1000)]));
+
+        //* When
+        let available_before = escrow.is_available(&indexer, 1000);
+        escrow.on_receipt(indexer, allocation, 600);
+        escrow.on_receipt(indexer, allocation, 300);
+        let available_outstanding = escrow.is_available(&indexer, 100);
+        let unavailable_outstanding = escrow.is_available(&indexer, 101);
+        escrow.on_rav(&allocation, 1, 600);
+        let available_after_rav = escrow.is_available(&indexer, 700);
+        // Aggregating the same receipts again doesn't reduce the outstanding value
+        escrow.on_rav(&allocation, 1, 600);
+        escrow.on_rav(&allocation, 2, 600);
+        let unavailable_after_repeated_rav = escrow.is_available(&indexer, 701);
+
+        //* Then
+        assert!(available_before);
+        assert!(available_outstanding);
+        assert!(!unavailable_outstanding);
+        assert!(available_after_rav);
+        assert!(!unavailable_after_repeated_rav);
+        assert!(!escrow.is_available(&IndexerId::from(Address::with_last_byte(2)), 1));
+        // Until the balances are known, indexers are only available if explicitly allowed
+        assert!(!Escrow::unknown(false).is_available(&indexer, 1));
+        assert!(Escrow::unknown(true).is_available(&indexer, u128::MAX));
+    }
+
+    #[test]
+    fn outstanding_value_is_restored_from_ledger() {
+        //* Given
+        let indexer = IndexerId::from(Address::with_last_byte(1));
+        let allocation = allocation_id!("89b23fea4e46d40e8a4c6cca723e2a03fdd4bec2");
+        let escrow = Escrow::new(HashMap::from([(indexer, 1000)]));
+        let totals = AllocationTotals {
+            indexer,
+            receipts: 3,
+            legacy_value: 0,
+            tap_value: 900,
+            rav_value: 600,
+            rav_timestamp_ns: 1,
+        };
+
+        //* When
+        escrow.restore(&HashMap::from([(allocation, totals)]));
+
+        //* Then
+        assert!(escrow.is_available(&indexer, 700));
+        assert!(!escrow.is_available(&indexer, 701));
+        // The restored RAV isn't applied again
+        escrow.on_rav(&allocation, 1, 600);
+        assert!(!escrow.is_available(&indexer, 701));
+        escrow.on_rav(&allocation, 2, 900);
+        assert!(escrow.is_available(&indexer, 1000));
+    }
+}
@@ -0,0 +1,339 @@
+//! Append-only ledger of the receipts issued by the gateway.
+//!
+//! Each issued receipt is appended, as a newline-delimited JSON record, to the current segment
+//! file in the ledger directory. A new segment is started on each restart, and once the current
+//! segment reaches `max_segment_bytes`. The records are written by a dedicated thread, which
+//! flushes and syncs the segment file after each batch of queued records. The totals per allocation
+//! are kept in memory, and rebuilt from the segments on startup. So they can be reconciled against
+//! the vouchers and RAVs that indexers later claim.
+
+use std::{
+    collections::HashMap,
+    fs::{self, File, OpenOptions},
+    io::{BufRead as _, BufReader, BufWriter, Write as _},
+    iter,
+    path::{Path, PathBuf},
+    sync::mpsc,
+    thread,
+};
+
+use anyhow::{anyhow, Context as _};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, DisplayFromStr};
+use thegraph_core::{AllocationId, IndexerId};
+
+use crate::time::unix_timestamp;
+
+/// The value of the receipts issued for an allocation, and of the latest RAV for its TAP receipts.
+#[serde_as]
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct AllocationTotals {
+    pub indexer: IndexerId,
+    pub receipts: u64,
+    /// Value of the legacy (Scalar) receipts, in GRT wei
+    #[serde_as(as = "DisplayFromStr")]
+    pub legacy_value: u128,
+    /// Value of the TAP receipts, in GRT wei
+    #[serde_as(as = "DisplayFromStr")]
+    pub tap_value: u128,
+    /// Aggregate value of the latest RAV signed by the gateway, in GRT wei
+    #[serde_as(as = "DisplayFromStr")]
+    pub rav_value: u128,
+    /// Timestamp, in nanoseconds, of the latest RAV signed by the gateway
+    pub rav_timestamp_ns: u64,
+}
+
+#[serde_as]
+#[derive(Serialize, Deserialize)]
+struct Record {
+    timestamp: u64,
+    indexer: IndexerId,
+    allocation: AllocationId,
+    #[serde_as(as = "DisplayFromStr")]
+    value: u128,
+    tap: bool,
+    /// Set for RAV records, where `value` is the aggregate value of the RAV
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    rav_timestamp_ns: Option<u64>,
+}
+
+pub struct ReceiptLedger {
+    totals: Mutex<HashMap<AllocationId, AllocationTotals>>,
+    /// Records queued for the writer thread. `None` once the ledger is dropped.
+    records: Option<mpsc::Sender<Record>>,
+    writer: Option<thread::JoinHandle<()>>,
+}
+
+struct Segment {
+    dir: PathBuf,
+    max_bytes: u64,
+    index: u64,
+    file: BufWriter<File>,
+    bytes: u64,
+    line_buf: Vec<u8>,
+}
+
+impl ReceiptLedger {
+    /// Open the ledger in the given directory, restoring the totals from its existing segments.
+    pub fn open(dir: PathBuf, max_segment_bytes: u64) -> anyhow::Result<Self> {
+        fs::create_dir_all(&dir)
+            .with_context(|| anyhow!("failed to create receipt ledger {}", dir.display()))?;
+        let segments = segments(&dir)?;
+        let mut totals = HashMap::new();
+        for (_, path) in &segments {
+            restore(path, &mut totals)
+                .with_context(|| anyhow!("failed to read receipt ledger {}", path.display()))?;
+        }
+        let index = segments.last().map(|(index, _)| index + 1).unwrap_or(0);
+        let segment = Segment::create(dir, max_segment_bytes, index)?;
+        let (tx, rx) = mpsc::channel();
+        let writer = thread::Builder::new()
+            .name("receipt-ledger".into())
+            .spawn(move || write_records(segment, rx))
+            .context("failed to spawn receipt ledger writer")?;
+        tracing::info!(
+            segments = segments.len(),
+            allocations = totals.len(),
+            "receipt ledger restored"
+        );
+        Ok(Self {
+            totals: Mutex::new(totals),
+            records: Some(tx),
+            writer: Some(writer),
+        })
+    }
+
+    /// Append the issued receipt to the ledger.
+    pub fn record(&self, indexer: IndexerId, allocation: AllocationId, value: u128, tap: bool) {
+        let record = Record {
+            timestamp: unix_timestamp(),
+            indexer,
+            allocation,
+            value,
+            tap,
+            rav_timestamp_ns: None,
+        };
+        self.append(record);
+    }
+
+    /// Append the RAV signed by the gateway to the ledger. RAVs for allocations without receipts
+    /// in the ledger are not recorded.
+    pub fn record_rav(&self, allocation: AllocationId, timestamp_ns: u64, value_aggregate: u128) {
+        let indexer = match self.totals.lock().get(&allocation) {
+            Some(totals) => totals.indexer,
+            None => return,
+        };
+        let record = Record {
+            timestamp: unix_timestamp(),
+            indexer,
+            allocation,
+            value: value_aggregate,
+            tap: true,
+            rav_timestamp_ns: Some(timestamp_ns),
+        };
+        self.append(record);
+    }
+
+    fn append(&self, record: Record) {
+        add(&mut self.totals.lock(), &record);
+        let sent = self.records.as_ref().map(|tx| tx.send(record).is_ok());
+        if sent != Some(true) {
+            tracing::error!(receipt_ledger_err = "writer stopped");
+        }
+    }
+
+    /// Returns the totals of the receipts issued for the allocation.
+    pub fn totals(&self, allocation: &AllocationId) -> Option<AllocationTotals> {
+        self.totals.lock().get(allocation).cloned()
+    }
+
+    /// Returns the totals of all allocations in the ledger.
+    pub fn all_totals(&self) -> HashMap<AllocationId, AllocationTotals> {
+        self.totals.lock().clone()
+    }
+}
+
+impl Drop for ReceiptLedger {
+    /// Wait for the queued records to be written.
+    fn drop(&mut self) {
+        drop(self.records.take());
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.join();
+        }
+    }
+}
+
+/// Write the records until the ledger is dropped. Blocks until the next record is queued, then
+/// appends all queued records before flushing and syncing the segment file.
+fn write_records(mut segment: Segment, records: mpsc::Receiver<Record>) {
+    while let Ok(record) = records.recv() {
+        for record in iter::once(record).chain(records.try_iter()) {
+            if let Err(receipt_ledger_err) = segment.append(&record) {
+                tracing::error!(receipt_ledger_err = format!("{receipt_ledger_err:#}"));
+            }
+        }
+        if let Err(receipt_ledger_err) = segment.sync() {
+            tracing::error!(receipt_ledger_err = format!("{receipt_ledger_err:#}"));
+        }
+    }
+}
+
+impl Segment {
+    fn create(dir: PathBuf, max_bytes: u64, index: u64) -> anyhow::Result<Self> {
+        let path = segment_path(&dir, index);
+        let file = OpenOptions::new()
+            .create_new(true)
+            .append(true)
+            .open(&path)
+            .with_context(|| anyhow!("failed to create receipt ledger {}", path.display()))?;
+        Ok(Self {
+            dir,
+            max_bytes,
+            index,
+            file: BufWriter::new(file),
+            bytes: 0,
+            line_buf: Default::default(),
+        })
+    }
+
+    fn append(&mut self, record: &Record) -> anyhow::Result<()> {
+        if (self.bytes > 0) && (self.bytes >= self.max_bytes) {
+            self.sync()?;
+            *self = Self::create(self.dir.clone(), self.max_bytes, self.index + 1)?;
+        }
+        serde_json::to_writer(&mut self.line_buf, record)?;
+        self.line_buf.push(b'\n');
+        let result = self.file.write_all(&self.line_buf);
+        self.bytes += self.line_buf.len() as u64;
+        self.line_buf.clear();
+        result.context("failed to write receipt ledger")
+    }
+
+    fn sync(&mut self) -> anyhow::Result<()> {
+        self.file
+            .flush()
+            .context("failed to write receipt ledger")?;
+        self.file
+            .get_ref()
+            .sync_data()
+            .context("failed to sync receipt ledger")
+    }
+}
+
+fn segment_path(dir: &Path, index: u64) -> PathBuf {
+    dir.join(format!("receipts-{index:010}.ndjson"))
+}
+
+/// Returns the segment files in the directory, in the order they were written.
+fn segments(dir: &Path) -> anyhow::Result<Vec<(u64, PathBuf)>> {
+    let mut segments: Vec<(u64, PathBuf)> = fs::read_dir(dir)
+        .context("failed to list receipt ledger")?
+        .filter_map(|entry| {
+            let path = entry.ok()?.path();
+            let index = path
+                .file_name()?
+                .to_str()?
+                .strip_prefix("receipts-")?
+                .strip_suffix(".ndjson")?
+                .parse()
+                .ok()?;
+            Some((index, path))
+        })
+        .collect();
+    segments.sort_unstable();
+    Ok(segments)
+}
+
+fn restore(
+    path: &Path,
+    totals: &mut HashMap<AllocationId, AllocationTotals>,
+) -> anyhow::Result<()> {
+    let file = File::open(path)?;
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        // The last record may be truncated, if the gateway stopped while writing it.
+        match serde_json::from_str::<Record>(&line) {
+            Ok(record) => add(totals, &record),
+            Err(receipt_ledger_err) => {
+                tracing::warn!(path = %path.display(), %receipt_ledger_err);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn add(totals: &mut HashMap<AllocationId, AllocationTotals>, record: &Record) {
+    let totals = totals
+        .entry(record.allocation)
+        .or_insert_with(|| AllocationTotals {
+            indexer: record.indexer,
+            receipts: 0,
+            legacy_value: 0,
+            tap_value: 0,
+            rav_value: 0,
+            rav_timestamp_ns: 0,
+        });
+    if let Some(timestamp_ns) = record.rav_timestamp_ns {
+        if timestamp_ns > totals.rav_timestamp_ns {
+            totals.rav_timestamp_ns = timestamp_ns;
+            totals.rav_value = record.value;
+        }
+        return;
+    }
+    totals.receipts += 1;
+    if record.tap {
+        totals.tap_value += record.value;
+    } else {
+        totals.legacy_value += record.value;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use thegraph_core::{allocation_id, Address};
+
+    use super::*;
+
+    #[test]
+    fn ledger_totals_are_restored() {
+        //* Given
+        let dir = std::env::temp_dir().join(format!("receipt-ledger-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let indexer = IndexerId::from(Address::ZERO);
+        let allocation = allocation_id!("89b23fea4e46d40e8a4c6cca723e2a03fdd4bec2");
+
+        //* When
+        let ledger = ReceiptLedger::open(dir.clone(), 1).expect("failed to open ledger");
+        ledger.record(indexer, allocation, 1000, true);
+        ledger.record(indexer, allocation, 2000, true);
+        ledger.record(indexer, allocation, 500, false);
+        ledger.record_rav(allocation, 2, 1000);
+        // Older RAVs don't replace the latest RAV
+        ledger.record_rav(allocation, 1, 500);
+        // RAVs for allocations without receipts are not recorded
+        let other_allocation = allocation_id!("f7a5dce1a3d6c0ecc6bd7ec1ad3c2a7e8e9c6c5b");
+        ledger.record_rav(other_allocation, 1, 500);
+        let totals = ledger.totals(&allocation);
+        drop(ledger);
+        let restored = ReceiptLedger::open(dir.clone(), 1).expect("failed to reopen ledger");
+        let restored_totals = restored.totals(&allocation);
+        let segments = segments(&dir).unwrap().len();
+        let _ = fs::remove_dir_all(&dir);
+
+        //* Then
+        let expected = AllocationTotals {
+            indexer,
+            receipts: 3,
+            legacy_value: 500,
+            tap_value: 3000,
+            rav_value: 1000,
+            rav_timestamp_ns: 2,
+        };
+        assert_eq!(totals, Some(expected.clone()));
+        assert_eq!(restored_totals, Some(expected));
+        assert_eq!(restored.totals(&other_allocation), None);
+        // 5 segments from rotation, and a new one on restart
+        assert_eq!(segments, 6);
+    }
+}
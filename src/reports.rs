@@ -3,10 +3,15 @@ use ordered_float::NotNan;
 use prost::Message;
 use serde_json::json;
 use thegraph_core::{Address, AllocationId, DeploymentId, IndexerId};
-use tokio::sync::mpsc;
+use tokio::sync::{mpsc, watch};
 use toolshed::concat_bytes;
 
-use crate::{errors, indexer_client::IndexerResponse, receipts::Receipt, time::unix_timestamp};
+pub use self::sink::{Encoding, FileSink, KafkaSink, MemorySink, ReportSink, StdoutSink};
+use crate::{
+    budgets::USD, errors, indexer_client::IndexerResponse, receipts::Receipt, time::unix_timestamp,
+};
+
+mod sink;
 
 pub struct ClientRequest {
     pub id: String,
@@ -37,13 +42,10 @@ pub struct IndexerRequest {
 pub struct Reporter {
     pub tap_signer: Address,
     pub graph_env: String,
-    pub budget: String,
+    pub budget: watch::Receiver<USD>,
     pub topics: Topics,
     pub write_buf: Vec<u8>,
-    pub kafka_producer: rdkafka::producer::ThreadedProducer<
-        rdkafka::producer::DefaultProducerContext,
-        rdkafka::producer::NoCustomPartitioner,
-    >,
+    pub sink: Box<dyn ReportSink>,
 }
 
 pub struct Topics {
@@ -58,21 +60,17 @@ impl Reporter {
     pub fn create(
         tap_signer: Address,
         graph_env: String,
-        budget: NotNan<f64>,
+        budget: watch::Receiver<USD>,
         topics: Topics,
-        kafka_config: impl Into<rdkafka::ClientConfig>,
-    ) -> anyhow::Result<mpsc::UnboundedSender<ClientRequest>> {
-        let kafka_producer = kafka_config
-            .into()
-            .create()
-            .context("kafka producer error")?;
+        sink: Box<dyn ReportSink>,
+    ) -> mpsc::UnboundedSender<ClientRequest> {
         let mut reporter = Self {
             tap_signer,
             graph_env,
-            budget: budget.to_string(),
+            budget,
             topics,
             write_buf: Default::default(),
-            kafka_producer,
+            sink,
         };
 
         let (tx, mut rx) = mpsc::unbounded_channel();
@@ -83,7 +81,7 @@ impl Reporter {
                 }
             }
         });
-        Ok(tx)
+        tx
     }
 
     fn report(&mut self, client_request: ClientRequest) -> anyhow::Result<()> {
@@ -108,7 +106,7 @@ impl Reporter {
                         "No indexers found for subgraph deployment".to_string(),
                         1621366907,
                     ),
-                    errors::Error::BadIndexers(_) => (
+                    errors::Error::BadIndexers(_) | errors::Error::StaleIndexers(_) => (
                         "No suitable indexer found for subgraph deployment".to_string(),
                         510359393,
                     ),
@@ -137,7 +135,7 @@ impl Reporter {
             "response_time_ms": client_request.response_time_ms,
             "request_bytes": client_request.request_bytes,
             "response_bytes": client_request.response_bytes,
-            "budget": self.budget.to_string(),
+            "budget": self.budget.borrow().0.to_string(),
             "query_count": 1,
             "fee": total_fees_grt as f32,
             "fee_usd": total_fees_usd as f32,
@@ -194,37 +192,17 @@ impl Reporter {
                 "status_code": legacy_status_code,
             });
             serde_json::to_writer(&mut self.write_buf, &indexer_request_payload).unwrap();
-            let record: rdkafka::producer::BaseRecord<(), [u8], ()> =
-                rdkafka::producer::BaseRecord::to(self.topics.indexer_request)
-                    .payload(&self.write_buf);
-            self.kafka_producer
-                .send(record)
-                .map_err(|(err, _)| err)
-                .context(anyhow!(
-                    "failed to send to topic {}",
-                    self.topics.indexer_request
-                ))?;
-            self.write_buf.clear();
+            self.send(self.topics.indexer_request, Encoding::Json)?;
 
-            if matches!(&indexer_request.receipt, Receipt::TAP(_)) {
+            if let Receipt::TAP(_, signer) = &indexer_request.receipt {
                 IndexerFeesProtobuf {
-                    signer: self.tap_signer.to_vec(),
+                    signer: signer.to_vec(),
                     receiver: indexer_request.indexer.to_vec(),
                     fee_grt: indexer_request.receipt.grt_value() as f64 * 1e-18,
                 }
                 .encode(&mut self.write_buf)
                 .unwrap();
-                let record: rdkafka::producer::BaseRecord<(), [u8], ()> =
-                    rdkafka::producer::BaseRecord::to(self.topics.indexer_fees)
-                        .payload(&self.write_buf);
-                self.kafka_producer
-                    .send(record)
-                    .map_err(|(err, _)| err)
-                    .context(anyhow!(
-                        "failed to send to topic {}",
-                        self.topics.indexer_fees
-                    ))?;
-                self.write_buf.clear();
+                self.send(self.topics.indexer_fees, Encoding::Protobuf)?;
             }
 
             if let Some((original_response, attestation)) = indexer_request
@@ -248,34 +226,24 @@ impl Reporter {
                 }
                 .encode(&mut self.write_buf)
                 .unwrap();
-                let record: rdkafka::producer::BaseRecord<(), [u8], ()> =
-                    rdkafka::producer::BaseRecord::to(self.topics.attestation)
-                        .payload(&self.write_buf);
-                self.kafka_producer
-                    .send(record)
-                    .map_err(|(err, _)| err)
-                    .context(anyhow!(
-                        "failed to send to topic {}",
-                        self.topics.attestation
-                    ))?;
-                self.write_buf.clear();
+                self.send(self.topics.attestation, Encoding::Protobuf)?;
             }
         }
 
         serde_json::to_writer(&mut self.write_buf, &client_request_payload).unwrap();
-        let record: rdkafka::producer::BaseRecord<(), [u8], ()> =
-            rdkafka::producer::BaseRecord::to(self.topics.client_request).payload(&self.write_buf);
-        self.kafka_producer
-            .send(record)
-            .map_err(|(err, _)| err)
-            .context(anyhow!(
-                "failed to send to topic {}",
-                self.topics.client_request
-            ))?;
-        self.write_buf.clear();
+        self.send(self.topics.client_request, Encoding::Json)?;
 
         Ok(())
     }
+
+    fn send(&mut self, topic: &'static str, encoding: Encoding) -> anyhow::Result<()> {
+        let result = self
+            .sink
+            .send(topic, encoding, &self.write_buf)
+            .context(anyhow!("failed to send to topic {topic}"));
+        self.write_buf.clear();
+        result
+    }
 }
 
 #[derive(Clone, PartialEq, prost::Message)]
@@ -312,3 +280,55 @@ pub struct IndexerFeesProtobuf {
     #[prost(double, tag = "3")]
     fee_grt: f64,
 }
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+
+    use super::*;
+
+    #[test]
+    fn client_request_report_is_sent_to_sink() {
+        //* Given
+        let sink = MemorySink::default();
+        let (_, budget) = watch::channel(USD(NotNan::new(20e-6).unwrap()));
+        let mut reporter = Reporter {
+            tap_signer: Address::ZERO,
+            graph_env: "test".to_string(),
+            budget,
+            topics: Topics {
+                client_request: "client_request",
+                indexer_request: "indexer_request",
+                attestation: "attestation",
+                indexer_fees: "indexer_fees",
+            },
+            write_buf: Default::default(),
+            sink: Box::new(sink.clone()),
+        };
+
+        //* When
+        let result = reporter.report(ClientRequest {
+            id: "request-id".to_string(),
+            response_time_ms: 10,
+            result: Ok(()),
+            api_key: "api-key".to_string(),
+            user: "user".to_string(),
+            grt_per_usd: NotNan::new(10.0).unwrap(),
+            indexer_requests: vec![],
+            request_bytes: 32,
+            response_bytes: Some(64),
+        });
+
+        //* Then
+        assert!(result.is_ok());
+        let records = sink.records.lock().unwrap();
+        assert_eq!(records.len(), 1);
+        let (topic, payload) = &records[0];
+        assert_eq!(*topic, "client_request");
+        let payload: Value = serde_json::from_slice(payload).expect("invalid JSON payload");
+        assert_eq!(payload["query_id"], "request-id");
+        assert_eq!(payload["graph_env"], "test");
+        assert_eq!(payload["status_code"], 0);
+        assert!(reporter.write_buf.is_empty());
+    }
+}
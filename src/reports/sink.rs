@@ -0,0 +1,222 @@
+//! Report sinks, the destinations of the gateway's reports.
+//!
+//! The Kafka sink sends each report, as is, to the topic it is reported on. The other sinks write
+//! each report as a newline-delimited JSON record of the form
+//! `{"topic": "...", "timestamp": 0, "payload": {...}}`. Protobuf payloads are hex-encoded under
+//! `payload_hex` instead of `payload`.
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write as _},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{anyhow, Context as _};
+use serde_json::json;
+
+use crate::time::unix_timestamp;
+
+/// The encoding of a report payload.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Encoding {
+    Json,
+    Protobuf,
+}
+
+/// A destination for the gateway's reports.
+pub trait ReportSink: Send + 'static {
+    fn send(
+        &mut self,
+        topic: &'static str,
+        encoding: Encoding,
+        payload: &[u8],
+    ) -> anyhow::Result<()>;
+}
+
+pub struct KafkaSink {
+    producer: rdkafka::producer::ThreadedProducer<
+        rdkafka::producer::DefaultProducerContext,
+        rdkafka::producer::NoCustomPartitioner,
+    >,
+}
+
+impl KafkaSink {
+    pub fn new(kafka_config: impl Into<rdkafka::ClientConfig>) -> anyhow::Result<Self> {
+        let producer = kafka_config
+            .into()
+            .create()
+            .context("kafka producer error")?;
+        Ok(Self { producer })
+    }
+}
+
+impl ReportSink for KafkaSink {
+    fn send(
+        &mut self,
+        topic: &'static str,
+        _encoding: Encoding,
+        payload: &[u8],
+    ) -> anyhow::Result<()> {
+        let record: rdkafka::producer::BaseRecord<(), [u8], ()> =
+            rdkafka::producer::BaseRecord::to(topic).payload(payload);
+        self.producer.send(record).map_err(|(err, _)| err)?;
+        Ok(())
+    }
+}
+
+/// Writes newline-delimited JSON records to a file. Once the file reaches `max_file_bytes`, it is
+/// renamed to `<path>.<unix timestamp ms>.<sequence number>` and a new file is started.
+pub struct FileSink {
+    path: PathBuf,
+    max_file_bytes: u64,
+    file: File,
+    file_bytes: u64,
+    line_buf: Vec<u8>,
+    /// Number of rotations since the sink was created, so that files rotated within the same
+    /// millisecond don't overwrite each other
+    rotations: u64,
+}
+
+impl FileSink {
+    pub fn new(path: PathBuf, max_file_bytes: u64) -> anyhow::Result<Self> {
+        let (file, file_bytes) = open_append(&path)
+            .with_context(|| anyhow!("failed to open report file {}", path.display()))?;
+        Ok(Self {
+            path,
+            max_file_bytes,
+            file,
+            file_bytes,
+            line_buf: Default::default(),
+            rotations: 0,
+        })
+    }
+
+    fn rotate(&mut self) -> anyhow::Result<()> {
+        self.file.flush()?;
+        let mut rotated_path = self.path.clone().into_os_string();
+        rotated_path.push(format!(".{}.{}", unix_timestamp(), self.rotations));
+        self.rotations += 1;
+        fs::rename(&self.path, &rotated_path).context("failed to rotate report file")?;
+        let (file, file_bytes) = open_append(&self.path).context("failed to open report file")?;
+        self.file = file;
+        self.file_bytes = file_bytes;
+        Ok(())
+    }
+}
+
+fn open_append(path: &Path) -> io::Result<(File, u64)> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let len = file.metadata()?.len();
+    Ok((file, len))
+}
+
+impl ReportSink for FileSink {
+    fn send(
+        &mut self,
+        topic: &'static str,
+        encoding: Encoding,
+        payload: &[u8],
+    ) -> anyhow::Result<()> {
+        if (self.file_bytes > 0) && (self.file_bytes >= self.max_file_bytes) {
+            self.rotate()?;
+        }
+        write_record(&mut self.line_buf, topic, encoding, payload)?;
+        self.file
+            .write_all(&self.line_buf)
+            .context("failed to write report file")?;
+        self.file_bytes += self.line_buf.len() as u64;
+        self.line_buf.clear();
+        Ok(())
+    }
+}
+
+/// Writes newline-delimited JSON records to stdout.
+#[derive(Default)]
+pub struct StdoutSink {
+    line_buf: Vec<u8>,
+}
+
+impl ReportSink for StdoutSink {
+    fn send(
+        &mut self,
+        topic: &'static str,
+        encoding: Encoding,
+        payload: &[u8],
+    ) -> anyhow::Result<()> {
+        write_record(&mut self.line_buf, topic, encoding, payload)?;
+        let result = io::stdout().lock().write_all(&self.line_buf);
+        self.line_buf.clear();
+        result.context("failed to write to stdout")
+    }
+}
+
+/// Keeps all reports in memory, as `(topic, payload)` pairs. Intended for tests.
+#[derive(Clone, Default)]
+pub struct MemorySink {
+    pub records: Arc<Mutex<Vec<(&'static str, Vec<u8>)>>>,
+}
+
+impl ReportSink for MemorySink {
+    fn send(
+        &mut self,
+        topic: &'static str,
+        _encoding: Encoding,
+        payload: &[u8],
+    ) -> anyhow::Result<()> {
+        self.records.lock().unwrap().push((topic, payload.to_vec()));
+        Ok(())
+    }
+}
+
+fn write_record(
+    buf: &mut Vec<u8>,
+    topic: &str,
+    encoding: Encoding,
+    payload: &[u8],
+) -> anyhow::Result<()> {
+    let record = match encoding {
+        Encoding::Json => {
+            let payload: serde_json::Value =
+                serde_json::from_slice(payload).context("invalid JSON payload")?;
+            json!({ "topic": topic, "timestamp": unix_timestamp(), "payload": payload })
+        }
+        Encoding::Protobuf => json!({
+            "topic": topic,
+            "timestamp": unix_timestamp(),
+            "payload_hex": hex::encode(payload),
+        }),
+    };
+    serde_json::to_writer(&mut *buf, &record)?;
+    buf.push(b'\n');
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_sink_rotates_files() {
+        //* Given
+        let dir = std::env::temp_dir().join(format!("report-sink-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("reports.ndjson");
+        let mut sink = FileSink::new(path.clone(), 1).expect("failed to create file sink");
+
+        //* When
+        sink.send("a", Encoding::Json, br#"{"n":1}"#).unwrap();
+        sink.send("a", Encoding::Json, br#"{"n":2}"#).unwrap();
+        sink.send("b", Encoding::Protobuf, &[0xab, 0xcd]).unwrap();
+        let files = fs::read_dir(&dir).unwrap().count();
+        let current = fs::read_to_string(&path).unwrap();
+        let _ = fs::remove_dir_all(&dir);
+
+        //* Then
+        // Files rotated within the same millisecond are kept
+        assert_eq!(files, 3);
+        let record: serde_json::Value = serde_json::from_str(current.trim_end()).unwrap();
+        assert_eq!(record["topic"], "b");
+        assert_eq!(record["payload_hex"], "abcd");
+    }
+}
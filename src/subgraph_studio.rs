@@ -7,7 +7,7 @@ use tokio::{
 };
 use url::Url;
 
-use crate::auth::{APIKey, QueryStatus};
+use crate::auth::{APIKey, QueryStatus, RateLimits};
 
 pub async fn api_keys(
     client: reqwest::Client,
@@ -59,6 +59,8 @@ impl Client {
             subgraphs: Vec<String>,
             #[serde(default)]
             domains: Vec<String>,
+            max_requests_per_second: Option<u32>,
+            max_requests_per_day: Option<u64>,
         }
 
         let response = self
@@ -84,6 +86,10 @@ impl Client {
                         .into_iter()
                         .filter_map(|s| s.parse().ok())
                         .collect(),
+                    rate_limits: RateLimits {
+                        max_requests_per_second: api_key.max_requests_per_second,
+                        max_requests_per_day: api_key.max_requests_per_day,
+                    },
                 };
                 (api_key.key.clone(), api_key)
             })
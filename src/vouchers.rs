@@ -5,11 +5,16 @@ use receipts::{self, combine_partial_vouchers, receipts_to_partial_voucher, rece
 use secp256k1::{PublicKey, Secp256k1, SecretKey};
 use serde::Deserialize;
 use serde_json::json;
+use tap_core::{
+    rav::ReceiptAggregateVoucher, receipt::Receipt as TapReceipt,
+    signed_message::EIP712SignedMessage,
+};
 use thegraph_core::Address;
 
 use crate::{
     json::{json_response, JsonResponse},
     metrics::METRICS,
+    receipts::ReceiptSigner,
 };
 
 lazy_static! {
@@ -165,6 +170,38 @@ fn process_voucher(signer: &SecretKey, payload: &Bytes) -> Result<JsonResponse,
     ))
 }
 
+pub async fn handle_rav_request(
+    State(signer): State<&'static ReceiptSigner>,
+    payload: Bytes,
+) -> Result<JsonResponse, (StatusCode, String)> {
+    let _timer = METRICS.rav_request.duration.start_timer();
+    match process_rav_request(signer, &payload) {
+        Ok(response) => {
+            METRICS.rav_request.ok.inc();
+            Ok(response)
+        }
+        Err(rav_request_err) => {
+            METRICS.rav_request.err.inc();
+            tracing::info!(%rav_request_err);
+            Err((StatusCode::BAD_REQUEST, rav_request_err))
+        }
+    }
+}
+
+fn process_rav_request(signer: &ReceiptSigner, payload: &Bytes) -> Result<JsonResponse, String> {
+    let request = serde_json::from_slice::<RavRequest>(payload).map_err(|err| err.to_string())?;
+    let rav = signer
+        .aggregate_receipts(&request.receipts, request.previous_rav)
+        .map_err(|err| format!("{err:#}"))?;
+    tracing::info!(
+        allocation = %rav.message.allocationId,
+        receipts = request.receipts.len(),
+        value_aggregate = %rav.message.valueAggregate,
+        "RAV request",
+    );
+    Ok(json_response([], serde_json::to_value(rav).unwrap()))
+}
+
 fn parse_receipts(payload: &[u8]) -> Result<([u8; 20], &[u8]), String> {
     if payload.len() < 20 {
         return Err("Invalid request data".into());
@@ -174,6 +211,12 @@ fn parse_receipts(payload: &[u8]) -> Result<([u8; 20], &[u8]), String> {
     Ok((allocation_id, &payload[20..]))
 }
 
+#[derive(Deserialize)]
+struct RavRequest {
+    receipts: Vec<EIP712SignedMessage<TapReceipt>>,
+    previous_rav: Option<EIP712SignedMessage<ReceiptAggregateVoucher>>,
+}
+
 #[derive(Deserialize)]
 #[serde(rename_all = "camelCase")]
 struct VoucherRequest {